@@ -0,0 +1,243 @@
+// finds and parses the ACPI tables the firmware leaves in physical memory
+use crate::memory;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::slice;
+use x86_64::PhysAddr;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::Madt;
+
+/// The size of the header every system description table starts with.
+const SDT_HEADER_SIZE: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP was found in the EBDA or the BIOS area.
+    NoRsdp,
+    /// The bytes of the structure with the given signature don't add up to 0.
+    InvalidChecksum([u8; 4]),
+    /// A table didn't have the signature it was referenced as.
+    InvalidSignature([u8; 4]),
+    /// A table is shorter than the fields it must contain.
+    TooShort([u8; 4]),
+}
+
+/// The tables found through the RSDP. Tables that are missing or invalid are
+/// `None`.
+#[derive(Debug)]
+pub struct AcpiTables {
+    /// The ACPI revision of the RSDP (0 for ACPI 1.0, 2 for later versions).
+    pub revision: u8,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    /// The physical addresses of all tables listed in the RSDT or XSDT.
+    pub table_addresses: Vec<PhysAddr>,
+}
+
+static ACPI_TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+
+/// Locates the RSDP and parses the tables it points to.
+///
+/// Must be called after `memory::init` and the heap initialization.
+pub fn init() -> Result<&'static AcpiTables, AcpiError> {
+    let tables = unsafe { parse_tables()? };
+    ACPI_TABLES.init_once(|| tables);
+    Ok(ACPI_TABLES.get().unwrap())
+}
+
+/// The parsed tables, `None` until `init` succeeded.
+pub fn tables() -> Option<&'static AcpiTables> {
+    ACPI_TABLES.try_get().ok()
+}
+
+/// The AML definition block of the DSDT, `None` if `init` didn't succeed or
+/// there is no valid DSDT.
+pub fn dsdt() -> Option<&'static [u8]> {
+    let fadt = tables()?.fadt.as_ref()?;
+    let dsdt = unsafe { sdt_bytes(fadt.dsdt, b"DSDT").ok()? };
+    Some(&dsdt[SDT_HEADER_SIZE..])
+}
+
+unsafe fn parse_tables() -> Result<AcpiTables, AcpiError> {
+    let rsdp_addr = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let rsdp = physical_bytes(rsdp_addr, 20);
+    let revision = rsdp[15];
+
+    // ACPI 2.0+ adds the XSDT with 64-bit table addresses
+    let (root, entry_size) = if revision >= 2 {
+        let length = read_u32(physical_bytes(rsdp_addr, 24), 20).unwrap() as usize;
+        check_sum(physical_bytes(rsdp_addr, length.max(36)), *b"RSD ")?;
+        let xsdt = PhysAddr::new(read_u64(physical_bytes(rsdp_addr, 32), 24).unwrap());
+        (sdt_bytes(xsdt, b"XSDT")?, 8)
+    } else {
+        check_sum(rsdp, *b"RSD ")?;
+        let rsdt = PhysAddr::new(read_u32(rsdp, 16).unwrap() as u64);
+        (sdt_bytes(rsdt, b"RSDT")?, 4)
+    };
+
+    let table_addresses: Vec<PhysAddr> = root[SDT_HEADER_SIZE..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => PhysAddr::new(read_u64(entry, 0).unwrap()),
+            _ => PhysAddr::new(read_u32(entry, 0).unwrap() as u64),
+        })
+        .collect();
+
+    let find = |signature: &[u8; 4]| {
+        table_addresses
+            .iter()
+            .find(|&&addr| &physical_bytes(addr, 4)[..] == signature)
+            .and_then(|&addr| sdt_bytes(addr, signature).ok())
+    };
+
+    Ok(AcpiTables {
+        revision,
+        madt: find(b"APIC").and_then(|table| Madt::parse(table).ok()),
+        fadt: find(b"FACP").and_then(|table| Fadt::parse(table).ok()),
+        hpet: find(b"HPET").and_then(|table| Hpet::parse(table).ok()),
+        table_addresses,
+    })
+}
+
+/// Searches the first KiB of the EBDA and the BIOS area below 1 MiB for the
+/// "RSD PTR " signature.
+unsafe fn find_rsdp() -> Option<PhysAddr> {
+    // the real mode segment of the EBDA is stored at 0x40e
+    let ebda_segment = read_u16(physical_bytes(PhysAddr::new(0x40e), 2), 0)?;
+    let ebda = ebda_segment as u64 * 16;
+    let areas = [(ebda, ebda + 1024), (0xe0000, 0x100000)];
+
+    areas
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let candidate = physical_bytes(addr, 20);
+            &candidate[..8] == b"RSD PTR " && check_sum(candidate, *b"RSD ").is_ok()
+        })
+}
+
+/// Returns the bytes of the table at `addr` after checking its signature
+/// and checksum.
+///
+/// This function is unsafe because `addr` must point to an ACPI table.
+unsafe fn sdt_bytes(
+    addr: PhysAddr,
+    signature: &[u8; 4],
+) -> Result<&'static [u8], AcpiError> {
+    let header = physical_bytes(addr, SDT_HEADER_SIZE);
+    if &header[..4] != signature {
+        return Err(AcpiError::InvalidSignature(*signature));
+    }
+    let length = read_u32(header, 4).unwrap() as usize;
+    if length < SDT_HEADER_SIZE {
+        return Err(AcpiError::TooShort(*signature));
+    }
+    let table = physical_bytes(addr, length);
+    check_sum(table, *signature)?;
+    Ok(table)
+}
+
+unsafe fn physical_bytes(addr: PhysAddr, len: usize) -> &'static [u8] {
+    slice::from_raw_parts(memory::phys_to_virt(addr).as_ptr(), len)
+}
+
+/// All bytes of an ACPI structure add up to 0 (mod 256).
+fn check_sum(bytes: &[u8], signature: [u8; 4]) -> Result<(), AcpiError> {
+    match bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) {
+        0 => Ok(()),
+        _ => Err(AcpiError::InvalidChecksum(signature)),
+    }
+}
+
+// little endian field accessors that return `None` past the end of a table
+
+fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// The address space a `GenericAddress` refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+/// A register location as described by ACPI's Generic Address Structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Parses the 12 byte structure at `offset`.
+    fn parse(bytes: &[u8], offset: usize) -> Option<GenericAddress> {
+        let address_space = match read_u8(bytes, offset)? {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfig,
+            other => AddressSpace::Other(other),
+        };
+        Some(GenericAddress {
+            address_space,
+            bit_width: read_u8(bytes, offset + 1)?,
+            bit_offset: read_u8(bytes, offset + 2)?,
+            access_size: read_u8(bytes, offset + 3)?,
+            address: read_u64(bytes, offset + 4)?,
+        })
+    }
+}
+
+/// Builds a table with a valid header and checksum around `body`.
+#[cfg(test)]
+fn test_table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend_from_slice(signature);
+    table.extend_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+    table.push(revision);
+    table.push(0); // checksum
+    table.resize(SDT_HEADER_SIZE, 0);
+    table.extend_from_slice(body);
+    let sum = table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    table[9] = 0u8.wrapping_sub(sum);
+    table
+}
+
+#[test_case]
+fn test_checksum() {
+    let table = test_table(b"TEST", 1, &[1, 2, 3]);
+    assert_eq!(check_sum(&table, *b"TEST"), Ok(()));
+
+    let mut corrupted = table.clone();
+    corrupted[SDT_HEADER_SIZE] ^= 0xff;
+    assert_eq!(
+        check_sum(&corrupted, *b"TEST"),
+        Err(AcpiError::InvalidChecksum(*b"TEST"))
+    );
+}
@@ -0,0 +1,111 @@
+// the Fixed ACPI Description Table describes the power management hardware
+use super::{read_u16, read_u32, read_u64, read_u8, AcpiError, GenericAddress};
+use x86_64::PhysAddr;
+
+/// The FADT flag telling that `reset_register` is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+/// The IA-PC boot architecture flag telling that there is an 8042 controller.
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub revision: u8,
+    /// The physical address of the DSDT.
+    pub dsdt: PhysAddr,
+    /// The ISA IRQ of the System Control Interrupt.
+    pub sci_interrupt: u16,
+    /// The port to write `acpi_enable` to to switch to ACPI mode, 0 if the
+    /// system is always in ACPI mode.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    // the I/O ports of the power management register blocks (0 if missing)
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm_timer_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    /// The CMOS RTC register holding the century, 0 if there is none.
+    pub century_register: u8,
+    pub boot_arch_flags: u16,
+    pub flags: u32,
+    /// The register to write `reset_value` to to reset the system.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    /// Parses the bytes of a table with the "FACP" signature.
+    pub fn parse(table: &[u8]) -> Result<Fadt, AcpiError> {
+        // fields up to the flags exist since ACPI 1.0, later fields depend on
+        // the length of the table
+        Fadt::parse_fields(table).ok_or(AcpiError::TooShort(*b"FACP"))
+    }
+
+    fn parse_fields(table: &[u8]) -> Option<Fadt> {
+        let flags = read_u32(table, 112)?;
+        let x_dsdt = read_u64(table, 140).filter(|&address| address != 0);
+        let dsdt = x_dsdt.unwrap_or(read_u32(table, 40)? as u64);
+        let reset_register = GenericAddress::parse(table, 116)
+            .filter(|_| flags & RESET_REG_SUP != 0);
+
+        Some(Fadt {
+            revision: read_u8(table, 8)?,
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: read_u16(table, 46)?,
+            smi_command_port: read_u32(table, 48)?,
+            acpi_enable: read_u8(table, 52)?,
+            acpi_disable: read_u8(table, 53)?,
+            pm1a_event_block: read_u32(table, 56)?,
+            pm1b_event_block: read_u32(table, 60)?,
+            pm1a_control_block: read_u32(table, 64)?,
+            pm1b_control_block: read_u32(table, 68)?,
+            pm_timer_block: read_u32(table, 76)?,
+            pm1_event_length: read_u8(table, 88)?,
+            pm1_control_length: read_u8(table, 89)?,
+            century_register: read_u8(table, 108)?,
+            boot_arch_flags: read_u16(table, 109)?,
+            flags,
+            reset_register,
+            reset_value: read_u8(table, 128).unwrap_or(0),
+        })
+    }
+
+    /// Whether the firmware reports an 8042 keyboard controller. ACPI 1.0
+    /// tables have no such flag, so they are assumed to have one.
+    pub fn has_8042(&self) -> bool {
+        self.revision < 2 || self.boot_arch_flags & BOOT_ARCH_8042 != 0
+    }
+}
+
+#[test_case]
+fn test_parse_fadt() {
+    use alloc::vec;
+
+    let mut body = vec![0u8; 244 - super::SDT_HEADER_SIZE];
+    let mut set = |offset: usize, bytes: &[u8]| {
+        let offset = offset - super::SDT_HEADER_SIZE;
+        body[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    set(40, &0x7fe0_0040u32.to_le_bytes()); // DSDT
+    set(46, &9u16.to_le_bytes()); // SCI
+    set(64, &0x604u32.to_le_bytes()); // PM1a control block
+    set(112, &RESET_REG_SUP.to_le_bytes());
+    set(116, &[1, 8, 0, 0]); // reset register in I/O space
+    set(120, &0xcf9u64.to_le_bytes());
+    set(128, &[0x0f]);
+    let table = super::test_table(b"FACP", 3, &body);
+    let fadt = Fadt::parse(&table).unwrap();
+
+    assert_eq!(fadt.dsdt, PhysAddr::new(0x7fe0_0040));
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_eq!(fadt.pm1a_control_block, 0x604);
+    assert_eq!(fadt.pm1b_control_block, 0);
+    let reset_register = fadt.reset_register.unwrap();
+    assert_eq!(reset_register.address_space, super::AddressSpace::SystemIo);
+    assert_eq!(reset_register.address, 0xcf9);
+    assert_eq!(fadt.reset_value, 0x0f);
+    assert!(!fadt.has_8042());
+}
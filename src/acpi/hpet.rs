@@ -0,0 +1,45 @@
+// the HPET table describes the High Precision Event Timer
+use super::{read_u16, read_u32, read_u8, AcpiError, AddressSpace, GenericAddress};
+use x86_64::PhysAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    /// The number of comparators (timers) of the HPET.
+    pub comparator_count: u8,
+    /// Whether the main counter is 64 bits wide.
+    pub counter_64_bit: bool,
+    /// Whether the HPET can replace the PIT and RTC interrupts.
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// The physical address of the memory mapped registers.
+    pub base_address: PhysAddr,
+    pub hpet_number: u8,
+    /// The minimum tick in periodic mode without losing interrupts.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    /// Parses the bytes of a table with the "HPET" signature.
+    pub fn parse(table: &[u8]) -> Result<Hpet, AcpiError> {
+        Hpet::parse_fields(table).ok_or(AcpiError::TooShort(*b"HPET"))
+    }
+
+    fn parse_fields(table: &[u8]) -> Option<Hpet> {
+        let block_id = read_u32(table, 36)?;
+        // the registers are always memory mapped
+        let base_address = GenericAddress::parse(table, 40)
+            .filter(|address| address.address_space == AddressSpace::SystemMemory)?;
+
+        Some(Hpet {
+            hardware_revision: block_id as u8,
+            comparator_count: ((block_id >> 8) & 0x1f) as u8 + 1,
+            counter_64_bit: block_id & (1 << 13) != 0,
+            legacy_replacement: block_id & (1 << 15) != 0,
+            pci_vendor_id: (block_id >> 16) as u16,
+            base_address: PhysAddr::new(base_address.address),
+            hpet_number: read_u8(table, 52)?,
+            minimum_tick: read_u16(table, 53)?,
+        })
+    }
+}
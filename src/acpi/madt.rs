@@ -0,0 +1,223 @@
+// the Multiple APIC Description Table lists the CPUs and interrupt controllers
+use super::{read_u16, read_u32, read_u64, read_u8, AcpiError, SDT_HEADER_SIZE};
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+// entry types
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    /// The ID the processor has in the ACPI namespace.
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// Whether the processor is ready to be used.
+    pub enabled: bool,
+    /// Whether a disabled processor can be enabled at runtime.
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt handled by this I/O APIC.
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// The default of the bus (active high for ISA).
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// The default of the bus (edge triggered for ISA).
+    Conforming,
+    Edge,
+    Level,
+}
+
+/// An ISA IRQ that is not identity mapped to a global system interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// A local APIC input that is connected to the NMI line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// The processor UID, `None` for all processors.
+    pub processor_uid: Option<u32>,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+    /// The LINT input (0 or 1).
+    pub lint: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// Whether the system also has the 8259 PICs, which need to be masked.
+    pub has_legacy_pics: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub interrupt_overrides: Vec<InterruptOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    /// Parses the bytes of a table with the "APIC" signature.
+    pub fn parse(table: &[u8]) -> Result<Madt, AcpiError> {
+        let too_short = AcpiError::TooShort(*b"APIC");
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(
+                read_u32(table, SDT_HEADER_SIZE).ok_or(too_short)? as u64,
+            ),
+            has_legacy_pics: read_u32(table, SDT_HEADER_SIZE + 4).ok_or(too_short)? & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            interrupt_overrides: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        // variable length entries, each starting with its type and length
+        let mut offset = SDT_HEADER_SIZE + 8;
+        while offset + 2 <= table.len() {
+            let length = table[offset + 1] as usize;
+            if length < 2 || offset + length > table.len() {
+                return Err(too_short);
+            }
+            madt.parse_entry(table[offset], &table[offset..offset + length])
+                .ok_or(too_short)?;
+            offset += length;
+        }
+        Ok(madt)
+    }
+
+    fn parse_entry(&mut self, entry_type: u8, entry: &[u8]) -> Option<()> {
+        match entry_type {
+            ENTRY_LOCAL_APIC => {
+                let flags = read_u32(entry, 4)?;
+                self.processors.push(Processor {
+                    processor_uid: read_u8(entry, 2)? as u32,
+                    apic_id: read_u8(entry, 3)? as u32,
+                    enabled: flags & 1 != 0,
+                    online_capable: flags & 2 != 0,
+                });
+            }
+            ENTRY_LOCAL_X2APIC => {
+                let flags = read_u32(entry, 8)?;
+                self.processors.push(Processor {
+                    processor_uid: read_u32(entry, 12)?,
+                    apic_id: read_u32(entry, 4)?,
+                    enabled: flags & 1 != 0,
+                    online_capable: flags & 2 != 0,
+                });
+            }
+            ENTRY_IO_APIC => self.io_apics.push(IoApicInfo {
+                id: read_u8(entry, 2)?,
+                address: PhysAddr::new(read_u32(entry, 4)? as u64),
+                gsi_base: read_u32(entry, 8)?,
+            }),
+            ENTRY_INTERRUPT_OVERRIDE => {
+                let (polarity, trigger_mode) = parse_mps_flags(read_u16(entry, 8)?);
+                self.interrupt_overrides.push(InterruptOverride {
+                    bus: read_u8(entry, 2)?,
+                    irq: read_u8(entry, 3)?,
+                    gsi: read_u32(entry, 4)?,
+                    polarity,
+                    trigger_mode,
+                });
+            }
+            ENTRY_LOCAL_APIC_NMI => {
+                let (polarity, trigger_mode) = parse_mps_flags(read_u16(entry, 3)?);
+                let processor_uid = read_u8(entry, 2)?;
+                self.local_apic_nmis.push(LocalApicNmi {
+                    processor_uid: (processor_uid != 0xff).then_some(processor_uid as u32),
+                    polarity,
+                    trigger_mode,
+                    lint: read_u8(entry, 5)?,
+                });
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                self.local_apic_address = PhysAddr::new(read_u64(entry, 4)?);
+            }
+            // other entry types are not needed
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// The override for the given ISA IRQ, if it isn't identity mapped.
+    pub fn interrupt_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.interrupt_overrides
+            .iter()
+            .find(|interrupt_override| interrupt_override.bus == 0 && interrupt_override.irq == irq)
+    }
+
+    /// The I/O APIC that handles the given global system interrupt, i.e. the
+    /// one with the highest `gsi_base` not above it.
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApicInfo> {
+        self.io_apics
+            .iter()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)
+    }
+}
+
+fn parse_mps_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::Conforming,
+    };
+    let trigger_mode = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Conforming,
+    };
+    (polarity, trigger_mode)
+}
+
+#[test_case]
+fn test_parse_madt() {
+    let body = [
+        0x00, 0x00, 0xe0, 0xfe, // local APIC address
+        0x01, 0x00, 0x00, 0x00, // flags: PC-AT compatible
+        0, 8, 0, 0, 1, 0, 0, 0, // processor 0 with APIC ID 0, enabled
+        0, 8, 1, 1, 0, 0, 0, 0, // processor 1 with APIC ID 1, disabled
+        1, 12, 0, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0, // I/O APIC at 0xfec00000
+        2, 10, 0, 0, 2, 0, 0, 0, 0, 0, // IRQ 0 -> GSI 2
+        2, 10, 0, 9, 9, 0, 0, 0, 0x0d, 0, // IRQ 9, active high, level triggered
+        4, 6, 0xff, 0, 0, 1, // NMI on LINT1 of all processors
+    ];
+    let table = super::test_table(b"APIC", 1, &body);
+    let madt = Madt::parse(&table).unwrap();
+
+    assert_eq!(madt.local_apic_address, PhysAddr::new(0xfee0_0000));
+    assert!(madt.has_legacy_pics);
+    assert_eq!(madt.processors.len(), 2);
+    assert!(madt.processors[0].enabled);
+    assert!(!madt.processors[1].enabled);
+    assert_eq!(madt.processors[1].apic_id, 1);
+    assert_eq!(madt.io_apic_for(2).map(|io_apic| io_apic.address), Some(PhysAddr::new(0xfec0_0000)));
+    assert_eq!(madt.interrupt_override(0).map(|o| o.gsi), Some(2));
+    assert_eq!(madt.interrupt_override(1), None);
+    let sci = madt.interrupt_override(9).unwrap();
+    assert_eq!((sci.polarity, sci.trigger_mode), (Polarity::ActiveHigh, TriggerMode::Level));
+    assert_eq!(madt.local_apic_nmis[0].processor_uid, None);
+    assert_eq!(madt.local_apic_nmis[0].lint, 1);
+}
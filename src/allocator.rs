@@ -1,22 +1,45 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
+use crate::{lock::{IrqSafeMutex, IrqSafeMutexGuard}, memory};
 
 pub mod bump;
 pub mod linked_list;
 pub mod fixed_size_block;
 pub mod buddy;
 
-use fixed_size_block::FixedSizeBlockAllocator;
+// the heap allocator is picked at compile time through the `alloc-*` cargo
+// features; without any of them the fixed size block allocator is used
+#[cfg(any(
+    all(feature = "alloc-bump", any(feature = "alloc-linked-list", feature = "alloc-fixed-block", feature = "alloc-buddy")),
+    all(feature = "alloc-linked-list", any(feature = "alloc-fixed-block", feature = "alloc-buddy")),
+    all(feature = "alloc-fixed-block", feature = "alloc-buddy"),
+))]
+compile_error!("only one of the `alloc-*` features can be enabled at a time");
+
+#[cfg(feature = "alloc-bump")]
+type HeapAllocator = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+type HeapAllocator = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-buddy")]
+type HeapAllocator = buddy::BuddyAllocator;
+#[cfg(not(any(feature = "alloc-bump", feature = "alloc-linked-list", feature = "alloc-buddy")))]
+type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
 
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: GrowableHeap<HeapAllocator> = GrowableHeap::new(HeapAllocator::new());
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, mapped at boot
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB, the heap never grows beyond this
+
+/// The heap grows by at least this much at once.
+const HEAP_GROW_SIZE: usize = 64 * 1024;
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -31,35 +54,138 @@ pub fn init_heap(
     };
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush()
-        };
+        map_heap_page(page, mapper, frame_allocator)?;
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.allocator.lock().init(HEAP_START, HEAP_SIZE);
     }
+    ALLOCATOR.size.store(HEAP_SIZE, Ordering::Relaxed);
 
     Ok(())
 }
 
-// needed to make the heap mutable
+/// Returns the number of bytes currently mapped for the heap.
+pub fn heap_size() -> usize {
+    ALLOCATOR.size.load(Ordering::Relaxed)
+}
+
+fn map_heap_page(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush()
+    };
+    Ok(())
+}
+
+/// A heap allocator that can take over memory at the end of its heap.
+pub trait GrowHeap {
+    /// Adds the `size` bytes starting at `heap_end`, the current end of the
+    /// heap, to the heap.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// memory is mapped and unused.
+    unsafe fn grow(&mut self, heap_end: usize, size: usize);
+}
+
+/// Wraps the heap allocator and maps more pages through
+/// `memory::KERNEL_MEMORY` whenever it runs out of memory.
+pub struct GrowableHeap<A> {
+    allocator: Locked<A>,
+    size: AtomicUsize,
+}
+
+impl<A: GrowHeap> GrowableHeap<A> {
+    pub const fn new(allocator: A) -> Self {
+        GrowableHeap {
+            allocator: Locked::new(allocator),
+            size: AtomicUsize::new(0),
+        }
+    }
+
+    /// Maps enough new pages at the end of the heap to fit an allocation
+    /// with the given layout and hands them to the allocator.
+    ///
+    /// Returns `false` if the heap reached `HEAP_MAX_SIZE` or no frames are left.
+    fn grow(&self, layout: Layout) -> bool {
+        let mut allocator = self.allocator.lock();
+        let mut kernel_memory = memory::KERNEL_MEMORY.lock();
+        let memory::KernelMemory { mapper, frame_allocator } = match kernel_memory.as_mut() {
+            Some(kernel_memory) => kernel_memory,
+            None => return false, // heap can't grow before the page table is handed over
+        };
+
+        // twice the size, so that a block aligned to the size fits in as well
+        let required_size = match layout.size().max(layout.align()).checked_mul(2) {
+            Some(size) => align_up(size, Size4KiB::SIZE as usize),
+            None => return false,
+        };
+        let heap_size = self.size.load(Ordering::Relaxed);
+        let grow_size = required_size.max(HEAP_GROW_SIZE).min(HEAP_MAX_SIZE - heap_size);
+        if grow_size < required_size {
+            return false;
+        }
+
+        // map page by page so that everything mapped before a failure still
+        // becomes part of the heap
+        let heap_end = HEAP_START + heap_size;
+        let mut mapped_size = 0;
+        while mapped_size < grow_size {
+            let page = Page::containing_address(VirtAddr::new((heap_end + mapped_size) as u64));
+            if map_heap_page(page, mapper, frame_allocator).is_err() {
+                break;
+            }
+            mapped_size += Size4KiB::SIZE as usize;
+        }
+
+        if mapped_size > 0 {
+            unsafe { allocator.grow(heap_end, mapped_size) };
+            self.size.store(heap_size + mapped_size, Ordering::Relaxed);
+        }
+        mapped_size >= required_size
+    }
+}
+
+unsafe impl<A: GrowHeap> GlobalAlloc for GrowableHeap<A>
+where
+    Locked<A>: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocator.alloc(layout);
+        if ptr.is_null() && self.grow(layout) {
+            self.allocator.alloc(layout)
+        } else {
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.allocator.dealloc(ptr, layout)
+    }
+}
+
+// needed to make the heap mutable; interrupts stay disabled while it's
+// locked, since a thread holding it must not be preempted
 pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+    inner: IrqSafeMutex<A>,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
-            inner: spin::Mutex::new(inner),
+            inner: IrqSafeMutex::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
+    #[track_caller]
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, A> {
         self.inner.lock()
     }
 }
@@ -1,34 +1,65 @@
+// a buddy allocator -> splits power-of-two blocks on alloc, merges them back on free
 use alloc::alloc::{GlobalAlloc, Layout};
-use lazy_static::lazy_static;
-use spin::Mutex;
-use core::{mem, ptr::{self, NonNull}};
-use super::{Locked, HEAP_SIZE};
+use core::{mem, ptr};
+use super::{align_up, GrowHeap, Locked};
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192];
+impl ListNode {
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+/// The size of the smallest block (order 0).
+///
+/// Every free block has to be able to hold a `ListNode`, so this must be at
+/// least `size_of::<ListNode>()` and a power of 2.
+const MIN_BLOCK_SIZE: usize = 8;
 
-fn list_index(layout: &Layout) -> Option<usize> {
-    let required_block_size = layout.size().max(layout.align());
-    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+/// The number of block orders. A block of order `n` is `MIN_BLOCK_SIZE << n`
+/// bytes, so the largest block is 512 MiB.
+const ORDERS: usize = 27;
+
+/// Returns the size of a block of the given order.
+const fn block_size(order: usize) -> usize {
+    MIN_BLOCK_SIZE << order
 }
 
-struct BuddyAllocator {
-    size: usize,
-    list_heads: [Option<&'static mut ListNode>; HEAP_SIZE],
-    fallback_allocator: linked_list_allocator::Heap,
+/// Choose the smallest block order that fits the given layout.
+///
+/// Blocks are naturally aligned to their size, so the alignment requirement
+/// is met by rounding up to the alignment too.
+fn order_for(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout
+        .size()
+        .max(layout.align())
+        .max(MIN_BLOCK_SIZE)
+        .checked_next_power_of_two()?;
+    let order = (required_block_size / MIN_BLOCK_SIZE).trailing_zeros() as usize;
+    if order < ORDERS {
+        Some(order)
+    } else {
+        None
+    }
+}
+
+pub struct BuddyAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    list_heads: [Option<&'static mut ListNode>; ORDERS],
 }
 
 impl BuddyAllocator {
-    /// Creates an empty FixedSizeBlockAllocator.
+    /// Creates an empty BuddyAllocator.
     pub const fn new() -> Self {
         const EMPTY: Option<&'static mut ListNode> = None;
         BuddyAllocator {
-            size: HEAP_SIZE,
-            list_heads: [EMPTY; HEAP_SIZE],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            heap_start: 0,
+            heap_end: 0,
+            list_heads: [EMPTY; ORDERS],
         }
     }
 
@@ -38,14 +69,150 @@ impl BuddyAllocator {
     /// heap bounds are valid and that the heap is unused. This method must be
     /// called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    /// Splits the given memory region into the largest naturally aligned
+    /// blocks that fit and puts them on the free lists.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        let end = addr + size;
+        let mut addr = align_up(addr, MIN_BLOCK_SIZE);
+
+        while addr + MIN_BLOCK_SIZE <= end {
+            // the largest order that is aligned at `addr` and still fits
+            let mut order = ORDERS - 1;
+            while addr % block_size(order) != 0 || addr + block_size(order) > end {
+                order -= 1;
+            }
+            self.push_block(order, addr);
+            addr += block_size(order);
+        }
     }
 
-    /// Allocates using the fallback allocator.
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+    /// Pushes the block at `addr` onto the free list of the given order.
+    unsafe fn push_block(&mut self, order: usize, addr: usize) {
+        // verify that block has size and alignment required for storing node
+        assert!(mem::size_of::<ListNode>() <= block_size(order));
+        assert!(mem::align_of::<ListNode>() <= block_size(order));
+
+        let new_node = ListNode {
+            next: self.list_heads[order].take(),
+        };
+        let new_node_ptr = addr as *mut ListNode;
+        new_node_ptr.write(new_node);
+        self.list_heads[order] = Some(&mut *new_node_ptr);
+    }
+
+    /// Pops any block off the free list of the given order.
+    fn pop_block(&mut self, order: usize) -> Option<usize> {
+        let node = self.list_heads[order].take()?;
+        self.list_heads[order] = node.next.take();
+        Some(node.start_addr())
+    }
+
+    /// Removes the block at `addr` from the free list of the given order.
+    ///
+    /// Returns `false` if the block is not free.
+    fn remove_block(&mut self, order: usize, addr: usize) -> bool {
+        let mut current = &mut self.list_heads[order];
+        while current.as_ref().map_or(false, |node| node.start_addr() != addr) {
+            current = &mut current.as_mut().unwrap().next;
+        }
+        match current.take() {
+            Some(node) => {
+                *current = node.next.take();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Allocates a block of the given order, splitting a larger block if no
+    /// block of that order is free.
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        // find the smallest free block that is large enough
+        let free_order = (order..ORDERS).find(|&o| self.list_heads[o].is_some())?;
+        let addr = self.pop_block(free_order)?;
+
+        // split it in halves until it has the requested size; the upper
+        // halves go back on the free lists
+        for split_order in (order..free_order).rev() {
+            unsafe { self.push_block(split_order, addr + block_size(split_order)) };
         }
+        Some(addr)
     }
-}
\ No newline at end of file
+
+    /// Frees the block at `addr`, merging it with its buddy as long as the
+    /// buddy is free as well.
+    unsafe fn free_block(&mut self, mut order: usize, mut addr: usize) {
+        while order + 1 < ORDERS {
+            // buddies only differ in the bit corresponding to their size
+            let buddy = addr ^ block_size(order);
+            if buddy < self.heap_start
+                || buddy + block_size(order) > self.heap_end
+                || !self.remove_block(order, buddy)
+            {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push_block(order, addr);
+    }
+}
+
+impl GrowHeap for BuddyAllocator {
+    unsafe fn grow(&mut self, heap_end: usize, size: usize) {
+        self.heap_end = heap_end + size;
+        self.add_free_region(heap_end, size);
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BuddyAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+
+        match order_for(&layout).and_then(|order| allocator.alloc_block(order)) {
+            Some(addr) => addr as *mut u8,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+
+        // the layout is the same as on allocation, so it maps to the same order
+        let order = order_for(&layout).expect("dealloc with invalid layout");
+        allocator.free_block(order, ptr as usize);
+    }
+}
+
+#[test_case]
+fn test_buddy_split_and_merge() {
+    #[repr(align(4096))]
+    struct Arena([u8; 4096]);
+    let mut arena = Arena([0; 4096]);
+
+    let allocator = Locked::new(BuddyAllocator::new());
+    let arena_start = arena.0.as_mut_ptr() as usize;
+    unsafe { allocator.lock().init(arena_start, 4096) };
+
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let whole = Layout::from_size_align(4096, 4096).unwrap();
+    unsafe {
+        // two minimal blocks are split off the arena and are buddies
+        let a = allocator.alloc(small);
+        let b = allocator.alloc(small);
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(a as usize ^ b as usize, MIN_BLOCK_SIZE);
+        assert!(allocator.alloc(whole).is_null());
+
+        // once both are freed, the arena is coalesced into a single block again
+        allocator.dealloc(a, small);
+        allocator.dealloc(b, small);
+        let c = allocator.alloc(whole);
+        assert_eq!(c as usize, arena_start);
+    }
+}
@@ -1,5 +1,5 @@
 // a bump allocator for this OS -> moves memory when new operations appear
-use super::{align_up, Locked};
+use super::{align_up, GrowHeap, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
@@ -32,6 +32,12 @@ impl BumpAllocator {
     }
 }
 
+impl GrowHeap for BumpAllocator {
+    unsafe fn grow(&mut self, heap_end: usize, size: usize) {
+        self.heap_end = heap_end + size;
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut bump = self.lock(); // get a mutable reference
@@ -1,6 +1,6 @@
 use alloc::alloc::{Layout, GlobalAlloc};
 use core::{mem, ptr::{self, NonNull}};
-use super::Locked;
+use super::{GrowHeap, Locked};
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
@@ -55,6 +55,13 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+impl GrowHeap for FixedSizeBlockAllocator {
+    unsafe fn grow(&mut self, _heap_end: usize, size: usize) {
+        // the fallback heap ends at `heap_end` and is extended in place
+        self.fallback_allocator.extend(size);
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // get a mutable version of the wrapped allocator instance
@@ -123,10 +123,16 @@ impl LinkedListAllocator {
     }
 }
 
-use super::Locked;
+use super::{GrowHeap, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
+impl GrowHeap for LinkedListAllocator {
+    unsafe fn grow(&mut self, heap_end: usize, size: usize) {
+        self.add_free_region(heap_end, size);
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // perform layout adjustments
@@ -5,31 +5,30 @@ use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 use x86_64::structures::gdt::SegmentSelector;
 use lazy_static::lazy_static;
-use core::ptr::addr_of;
+use core::ptr::{addr_of, addr_of_mut};
+use crate::memory::stack::{self, StackError};
+use alloc::boxed::Box;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    // contains stacks
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACK) });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+// contains stacks; the boot stack below is used for the double fault handler
+// until `init_ist_stacks` swaps in a guard-paged stack
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn boot_double_fault_stack() -> VirtAddr {
+    const STACK_SIZE: usize = 4096 * 5;
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACK) });
+    let stack_end = stack_start + STACK_SIZE;
+    stack_end
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
@@ -40,12 +39,66 @@ struct Selectors {
 }
 
 pub fn init() {
+    unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            boot_double_fault_stack();
+    }
+    load(&GDT.0, &GDT.1);
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
-    
-    GDT.0.load();
+
+    gdt.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(selectors.code_selector);
+        load_tss(selectors.tss_selector);
+    }
+}
+
+/// The GDT and TSS of an application processor, whose IST has stacks of its
+/// own. Never freed, since the processor runs until shutdown.
+pub struct ApGdt {
+    gdt: &'static GlobalDescriptorTable,
+    selectors: Selectors,
+}
+
+impl ApGdt {
+    /// Must be called after `memory::init_kernel_memory`.
+    pub fn new() -> Result<ApGdt, StackError> {
+        let double_fault_stack = stack::alloc_stack(stack::DEFAULT_STACK_PAGES)?;
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack.top();
+        let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+        Ok(ApGdt {
+            gdt: Box::leak(Box::new(gdt)),
+            selectors: Selectors { code_selector, tss_selector },
+        })
+    }
+
+    /// Loads the tables on the executing processor.
+    pub fn load(&self) {
+        load(self.gdt, &self.selectors);
     }
+}
+
+/// Replaces the boot stacks in the IST with guard-paged kernel stacks, so
+/// that an overflowing interrupt handler faults instead of corrupting memory.
+///
+/// Must be called after `memory::init_kernel_memory`.
+pub fn init_ist_stacks() -> Result<(), StackError> {
+    use x86_64::instructions::interrupts;
+
+    // the stack stays in use until shutdown, so it is never freed
+    let double_fault_stack = stack::alloc_stack(stack::DEFAULT_STACK_PAGES)?;
+    interrupts::without_interrupts(|| unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            double_fault_stack.top();
+    });
+    Ok(())
 }
\ No newline at end of file
@@ -1,39 +1,48 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use lazy_static::lazy_static;
-use crate::{gdt, println, write_cursor};
-use spin::Mutex;
+use crate::lock::IrqSafeMutex;
+use crate::write_cursor;
+
+pub mod apic;
+mod exceptions;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Rtc = PIC_1_OFFSET + 8,
+    // raised by the local APIC without needing an EOI
+    Spurious = 0xff,
 }
 
 pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-}
+pub static TICKER: IrqSafeMutex<u32> = IrqSafeMutex::new(0);
+pub static TICKER_BOOLEAN: IrqSafeMutex<bool> = IrqSafeMutex::new(true);
 
-lazy_static! {
-    pub static ref TICKER: Mutex<u32> = Mutex::new(0);
-    pub static ref TICKER_BOOLEAN: Mutex<bool> = Mutex::new(true);
-}
+/// The cursor blinks at about the default rate of the PIT it was made for.
+const CURSOR_INTERVAL_TICKS: u64 = crate::time::TIMER_FREQUENCY_HZ as u64 / 18;
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    write_cursor!();
+    crate::smp::count_tick();
+    // the clock, the timers and the threads are run by the bootstrap processor
+    if !crate::smp::is_bsp() {
+        end_of_interrupt(InterruptIndex::Timer);
+        return;
+    }
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer as u8);
+    let ticks = crate::time::tick();
+    crate::task::timer::expire(ticks);
+    if ticks % CURSOR_INTERVAL_TICKS == 0 {
+        write_cursor!();
     }
+
+    end_of_interrupt(InterruptIndex::Timer);
+    // may switch to another thread, so the interrupt must be acknowledged
+    crate::thread::tick(ticks);
 }
 
 
@@ -47,8 +56,8 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     drop(ticker_guard);
 
     lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
+        static ref KEYBOARD: IrqSafeMutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
+            IrqSafeMutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
                 HandleControl::Ignore)
             );
     }
@@ -57,27 +66,26 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
-use x86_64::structures::idt::PageFaultErrorCode;
-use crate::hlt_loop;
-
-// handle page faults!
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+extern "x86-interrupt" fn rtc_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::time::rtc::handle_interrupt();
+
+    end_of_interrupt(InterruptIndex::Rtc);
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+/// Signals the end of the interrupt to the APIC, or to the PICs as long as
+/// the APIC is not enabled.
+fn end_of_interrupt(index: InterruptIndex) {
+    match apic::local_apic() {
+        Some(local_apic) if apic::is_enabled() => local_apic.end_of_interrupt(),
+        _ => unsafe {
+            PICS.lock().notify_end_of_interrupt(index as u8);
+        },
+    }
 }
 
 // lets us accesss PICs
@@ -86,7 +94,7 @@ use pic8259::ChainedPics;
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> = spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqSafeMutex<ChainedPics> = IrqSafeMutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 #[test_case]
 fn test_breakpoint_exception() {
@@ -94,17 +102,24 @@ fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
 
+#[test_case]
+fn test_breakpoint_preserves_registers() {
+    use core::arch::asm;
+
+    // r11 is caller-saved, so only the entry stub keeps it intact
+    let value: u64;
+    unsafe {
+        asm!("mov r11, 0x1234", "int3", "mov {}, r11", out(reg) value, out("r11") _);
+    }
+    assert_eq!(value, 0x1234);
+}
+
 lazy_static! {
     // IDT lets us access the interrupt descriptor table to handle CPU errors
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        // handle breakpoints
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            // handle double faults
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
+        // handle all CPU exceptions (breakpoints, double faults, page faults, ...)
+        exceptions::install(&mut idt);
 
         // handle timer interrupts
         idt[InterruptIndex::Timer as usize]
@@ -114,7 +129,12 @@ lazy_static! {
         idt[InterruptIndex::Keyboard as usize]
             .set_handler_fn(keyboard_interrupt_handler);
 
-        idt.page_fault.set_handler_fn(page_fault_handler); 
+        // handle the periodic interrupt of the RTC
+        idt[InterruptIndex::Rtc as usize]
+            .set_handler_fn(rtc_interrupt_handler);
+
+        idt[InterruptIndex::Spurious as usize]
+            .set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -0,0 +1,374 @@
+// Local APIC and I/O APIC support; once enabled they take over from the 8259 PICs
+use super::{InterruptIndex, PICS};
+use crate::{
+    acpi::{
+        self,
+        madt::{Polarity, TriggerMode},
+    },
+    lock::IrqSafeMutex,
+    memory,
+    time::{pit, TIMER_FREQUENCY_HZ},
+};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    arch::x86_64::__cpuid,
+    hint::spin_loop,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{mapper::MapToError, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// The standard address of the I/O APIC, used if there is no MADT.
+const DEFAULT_IO_APIC_ADDRESS: u64 = 0xfec0_0000;
+const KEYBOARD_IRQ: u8 = 1;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// local APIC register offsets
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS: usize = 0xf0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_ERROR: usize = 0x370;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// interrupt command register
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+// I/O APIC registers
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// The CPU has no local APIC.
+    Unsupported,
+    /// Mapping the registers failed.
+    Map(MapToError<Size4KiB>),
+}
+
+/// The local APIC of the executing CPU.
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    unsafe fn read(&self, register: usize) -> u32 {
+        ptr::read_volatile((self.base + register).as_ptr::<u32>())
+    }
+
+    unsafe fn write(&self, register: usize, value: u32) {
+        ptr::write_volatile((self.base + register).as_mut_ptr::<u32>(), value)
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(LAPIC_ID) } >> 24) as u8
+    }
+
+    /// Signals the end of the current interrupt.
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(LAPIC_EOI, 0) };
+    }
+
+    unsafe fn enable(&self) {
+        // accept all interrupts, mask the legacy ExtINT and the error interrupts
+        self.write(LAPIC_TASK_PRIORITY, 0);
+        self.write(LAPIC_LVT_LINT0, LVT_MASKED);
+        self.write(LAPIC_LVT_ERROR, LVT_MASKED);
+        self.write(
+            LAPIC_SPURIOUS,
+            InterruptIndex::Spurious as u32 | SPURIOUS_APIC_ENABLE,
+        );
+    }
+
+    /// Counts how often the timer ticks per second (divided by 16) while
+    /// channel 2 of the PIT counts down 10 ms.
+    unsafe fn calibrate_timer(&self) -> u32 {
+        const CALIBRATION_PERIODS_PER_SECOND: u32 = 100;
+
+        pit::start_countdown((pit::PIT_FREQUENCY_HZ / CALIBRATION_PERIODS_PER_SECOND) as u16);
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+        while !pit::countdown_finished() {}
+        let elapsed = u32::MAX - self.read(LAPIC_TIMER_CURRENT_COUNT);
+        self.write(LAPIC_TIMER_INITIAL_COUNT, 0);
+
+        elapsed * CALIBRATION_PERIODS_PER_SECOND
+    }
+
+    /// Starts the timer in periodic mode, raising `vector` every
+    /// `ticks_per_period` ticks (divided by 16).
+    unsafe fn start_timer(&self, vector: u8, ticks_per_period: u32) {
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LAPIC_LVT_TIMER, vector as u32 | LVT_TIMER_PERIODIC);
+        self.write(LAPIC_TIMER_INITIAL_COUNT, ticks_per_period);
+    }
+
+    /// Sends an inter-processor interrupt to the local APIC with the ID
+    /// `apic_id` and waits until it was accepted.
+    unsafe fn send_ipi(&self, apic_id: u8, command: u32) {
+        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+        self.write(LAPIC_ICR_LOW, command);
+        while self.read(LAPIC_ICR_LOW) & ICR_SEND_PENDING != 0 {
+            spin_loop();
+        }
+    }
+
+    /// Resets the processor with the local APIC `apic_id`; it waits for a
+    /// startup IPI afterwards.
+    ///
+    /// This function is unsafe because the processor is stopped wherever it
+    /// is, so it must not be running the kernel.
+    pub unsafe fn send_init(&self, apic_id: u8) {
+        self.send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+    }
+
+    /// Starts the processor with the local APIC `apic_id` in real mode at
+    /// the physical address `page * 4096`.
+    ///
+    /// This function is unsafe because the processor must wait for a startup
+    /// IPI, and code to start it must be at the address.
+    pub unsafe fn send_startup(&self, apic_id: u8, page: u8) {
+        self.send_ipi(apic_id, ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+    }
+}
+
+/// Routes the global system interrupts `gsi_base..gsi_base + irq_count()`
+/// to local APICs.
+pub struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    irq_count: u8,
+}
+
+impl IoApic {
+    /// This function is unsafe because `base` must be the mapped registers
+    /// of an I/O APIC.
+    unsafe fn new(base: VirtAddr, gsi_base: u32) -> IoApic {
+        let mut io_apic = IoApic {
+            base,
+            gsi_base,
+            irq_count: 0,
+        };
+        let max_redirection_entry = (io_apic.read(IO_APIC_VERSION) >> 16) as u8;
+        io_apic.irq_count = max_redirection_entry + 1;
+        io_apic
+    }
+
+    unsafe fn read(&mut self, register: u32) -> u32 {
+        // select the register, then access it through the data window
+        ptr::write_volatile(self.base.as_mut_ptr::<u32>(), register);
+        ptr::read_volatile((self.base + 0x10u64).as_ptr::<u32>())
+    }
+
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        ptr::write_volatile(self.base.as_mut_ptr::<u32>(), register);
+        ptr::write_volatile((self.base + 0x10u64).as_mut_ptr::<u32>(), value);
+    }
+
+    /// The number of interrupt inputs of this I/O APIC.
+    pub fn irq_count(&self) -> u8 {
+        self.irq_count
+    }
+
+    /// Delivers the input `irq` as `vector` to the local APIC with the ID
+    /// `apic_id` (fixed delivery).
+    pub fn set_irq(
+        &mut self,
+        irq: u8,
+        vector: u8,
+        apic_id: u8,
+        polarity: Polarity,
+        trigger_mode: TriggerMode,
+    ) {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * irq as u32;
+        let mut entry = vector as u32;
+        if polarity == Polarity::ActiveLow {
+            entry |= REDIRECTION_ACTIVE_LOW;
+        }
+        if trigger_mode == TriggerMode::Level {
+            entry |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        unsafe {
+            self.write(register + 1, (apic_id as u32) << 24);
+            self.write(register, entry);
+        }
+    }
+
+    pub fn mask_irq(&mut self, irq: u8) {
+        let register = IO_APIC_REDIRECTION_TABLE + 2 * irq as u32;
+        unsafe {
+            let value = self.read(register);
+            self.write(register, value | REDIRECTION_MASKED);
+        }
+    }
+}
+
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+/// The period of the local APIC timers, calibrated by `init`.
+static TIMER_TICKS_PER_PERIOD: AtomicU32 = AtomicU32::new(0);
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: OnceCell<IrqSafeMutex<Vec<IoApic>>> = OnceCell::uninit();
+
+/// Whether interrupts are delivered through the APIC instead of the PICs.
+pub fn is_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::Acquire)
+}
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try_get().ok()
+}
+
+/// Delivers the ISA IRQ `irq` as `vector` to the local APIC of this CPU,
+/// taking the interrupt source overrides of the MADT into account.
+///
+/// Returns `false` if the APIC is not enabled or no I/O APIC handles the IRQ.
+pub fn route_isa_irq(irq: u8, vector: u8) -> bool {
+    let (Some(local_apic), Ok(io_apics)) = (local_apic(), IO_APICS.try_get()) else {
+        return false;
+    };
+    // ISA IRQs are identity mapped to active high, edge triggered global
+    // system interrupts unless overridden
+    let interrupt_override = acpi::tables()
+        .and_then(|tables| tables.madt.as_ref())
+        .and_then(|madt| madt.interrupt_override(irq));
+    let (gsi, polarity, trigger_mode) = match interrupt_override {
+        Some(o) => (o.gsi, o.polarity, o.trigger_mode),
+        None => (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge),
+    };
+
+    let mut io_apics = io_apics.lock();
+    let io_apic = io_apics
+        .iter_mut()
+        .filter(|io_apic| io_apic.gsi_base <= gsi)
+        .max_by_key(|io_apic| io_apic.gsi_base);
+    match io_apic {
+        Some(io_apic) if gsi - io_apic.gsi_base < io_apic.irq_count() as u32 => {
+            let input = (gsi - io_apic.gsi_base) as u8;
+            io_apic.set_irq(input, vector, local_apic.id(), polarity, trigger_mode);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn has_apic() -> bool {
+    // CPUID.01h:EDX[9]
+    let cpuid = unsafe { __cpuid(1) };
+    cpuid.edx & (1 << 9) != 0
+}
+
+/// Switches interrupt delivery from the 8259 PICs to the APIC: masks the
+/// PICs, starts the local APIC timer and routes the keyboard IRQ through the
+/// I/O APIC.
+///
+/// Must be called after `memory::init_kernel_memory`. The I/O APICs are taken
+/// from the MADT if `acpi::init` was called before. On error the PICs stay in
+/// use.
+pub fn init() -> Result<(), ApicError> {
+    use x86_64::instructions::interrupts;
+
+    if !has_apic() {
+        return Err(ApicError::Unsupported);
+    }
+
+    let mut apic_base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    let apic_base = unsafe { apic_base_msr.read() };
+    let local_apic_base = unsafe {
+        memory::map_mmio(PhysAddr::new(apic_base & APIC_BASE_ADDRESS_MASK), 4096)
+    }
+    .map_err(ApicError::Map)?;
+
+    let madt = acpi::tables().and_then(|tables| tables.madt.as_ref());
+    let io_apic_infos = match madt {
+        Some(madt) => madt
+            .io_apics
+            .iter()
+            .map(|io_apic| (io_apic.address, io_apic.gsi_base))
+            .collect(),
+        None => alloc::vec![(PhysAddr::new(DEFAULT_IO_APIC_ADDRESS), 0)],
+    };
+    let mut io_apics = Vec::with_capacity(io_apic_infos.len());
+    for (address, gsi_base) in io_apic_infos {
+        let base = unsafe { memory::map_mmio(address, 0x20) }.map_err(ApicError::Map)?;
+        io_apics.push(unsafe { IoApic::new(base, gsi_base) });
+    }
+    // without the legacy PICs there is nothing to mask
+    let has_legacy_pics = madt.map_or(true, |madt| madt.has_legacy_pics);
+
+    // the handlers must see the APIC enabled as soon as its timer runs
+    let keyboard_routed = interrupts::without_interrupts(|| {
+        unsafe {
+            if has_legacy_pics {
+                PICS.lock().disable();
+            }
+            apic_base_msr.write(apic_base | APIC_BASE_ENABLE);
+        }
+
+        LOCAL_APIC.init_once(|| LocalApic { base: local_apic_base });
+        let local_apic = local_apic().unwrap();
+        unsafe {
+            local_apic.enable();
+            let ticks_per_period = local_apic.calibrate_timer() / TIMER_FREQUENCY_HZ;
+            TIMER_TICKS_PER_PERIOD.store(ticks_per_period, Ordering::Relaxed);
+            local_apic.start_timer(InterruptIndex::Timer as u8, ticks_per_period);
+        }
+
+        for io_apic in io_apics.iter_mut() {
+            for irq in 0..io_apic.irq_count() {
+                io_apic.mask_irq(irq);
+            }
+        }
+        IO_APICS.init_once(|| IrqSafeMutex::new(io_apics));
+
+        APIC_ENABLED.store(true, Ordering::Release);
+        route_isa_irq(KEYBOARD_IRQ, InterruptIndex::Keyboard as u8)
+    });
+
+    if !keyboard_routed {
+        crate::println!("no I/O APIC handles the keyboard IRQ");
+    }
+    Ok(())
+}
+
+/// Enables the local APIC of an application processor and starts its timer
+/// at the rate `init` calibrated on the bootstrap processor.
+///
+/// Must be called on the application processor, after `init` succeeded.
+pub fn init_ap() {
+    let local_apic = local_apic().expect("APIC not initialized");
+    let mut apic_base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    unsafe {
+        let apic_base = apic_base_msr.read();
+        apic_base_msr.write(apic_base | APIC_BASE_ENABLE);
+        local_apic.enable();
+        local_apic.start_timer(
+            InterruptIndex::Timer as u8,
+            TIMER_TICKS_PER_PERIOD.load(Ordering::Relaxed),
+        );
+    }
+}
@@ -0,0 +1,319 @@
+// handlers for all CPU exceptions: every vector enters through an assembly stub
+// that saves the general-purpose registers, so that fatal exceptions can dump them
+use core::{arch::global_asm, fmt};
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{
+        InterruptDescriptorTable, InterruptStackFrameValue, PageFaultErrorCode, SelectorErrorCode,
+    },
+    VirtAddr,
+};
+use crate::{gdt, memory, println, serial_println};
+
+/// The general-purpose registers at the time of the exception, in the order
+/// the entry stub pushes them.
+#[repr(C)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "RAX={:#018x} RBX={:#018x} RCX={:#018x}", self.rax, self.rbx, self.rcx)?;
+        writeln!(f, "RDX={:#018x} RSI={:#018x} RDI={:#018x}", self.rdx, self.rsi, self.rdi)?;
+        writeln!(f, "RBP={:#018x} R8 ={:#018x} R9 ={:#018x}", self.rbp, self.r8, self.r9)?;
+        writeln!(f, "R10={:#018x} R11={:#018x} R12={:#018x}", self.r10, self.r11, self.r12)?;
+        write!(f, "R13={:#018x} R14={:#018x} R15={:#018x}", self.r13, self.r14, self.r15)
+    }
+}
+
+/// Everything on the stack when the entry stub calls `exception_dispatch`.
+#[repr(C)]
+struct ExceptionFrame {
+    registers: Registers,
+    vector: u64,
+    /// Pushed by the CPU, or 0 by the stub for vectors without error code.
+    error_code: u64,
+    stack_frame: InterruptStackFrameValue,
+}
+
+const DEBUG: u64 = 1;
+const NON_MASKABLE_INTERRUPT: u64 = 2;
+const BREAKPOINT: u64 = 3;
+const INVALID_TSS: u64 = 10;
+const SEGMENT_NOT_PRESENT: u64 = 11;
+const STACK_SEGMENT_FAULT: u64 = 12;
+const GENERAL_PROTECTION_FAULT: u64 = 13;
+const PAGE_FAULT: u64 = 14;
+const CP_PROTECTION_EXCEPTION: u64 = 21;
+
+fn exception_name(vector: u64) -> &'static str {
+    match vector {
+        0 => "DIVIDE ERROR",
+        1 => "DEBUG",
+        2 => "NON-MASKABLE INTERRUPT",
+        3 => "BREAKPOINT",
+        4 => "OVERFLOW",
+        5 => "BOUND RANGE EXCEEDED",
+        6 => "INVALID OPCODE",
+        7 => "DEVICE NOT AVAILABLE",
+        8 => "DOUBLE FAULT",
+        10 => "INVALID TSS",
+        11 => "SEGMENT NOT PRESENT",
+        12 => "STACK-SEGMENT FAULT",
+        13 => "GENERAL PROTECTION FAULT",
+        14 => "PAGE FAULT",
+        16 => "X87 FLOATING-POINT EXCEPTION",
+        17 => "ALIGNMENT CHECK",
+        18 => "MACHINE CHECK",
+        19 => "SIMD FLOATING-POINT EXCEPTION",
+        20 => "VIRTUALIZATION EXCEPTION",
+        21 => "CONTROL PROTECTION EXCEPTION",
+        28 => "HYPERVISOR INJECTION EXCEPTION",
+        29 => "VMM COMMUNICATION EXCEPTION",
+        30 => "SECURITY EXCEPTION",
+        _ => "UNKNOWN EXCEPTION",
+    }
+}
+
+/// Prints the error code of the exception broken up into its fields.
+struct DecodedErrorCode {
+    vector: u64,
+    error_code: u64,
+}
+
+impl fmt::Display for DecodedErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let error_code = self.error_code;
+        match self.vector {
+            STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT if error_code == 0 => {
+                write!(f, "0 (not caused by a segment selector)")
+            }
+            INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT => {
+                write!(f, "{:#x} {:?}", error_code, SelectorErrorCode::new_truncate(error_code))
+            }
+            PAGE_FAULT => write!(
+                f,
+                "{:#x} {:?}",
+                error_code,
+                PageFaultErrorCode::from_bits_truncate(error_code)
+            ),
+            CP_PROTECTION_EXCEPTION => {
+                let cause = match error_code & 0x7fff {
+                    1 => "NEAR-RET",
+                    2 => "FAR-RET/IRET",
+                    3 => "ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown",
+                };
+                write!(f, "{:#x} ({})", error_code, cause)
+            }
+            _ => write!(f, "{:#x}", error_code),
+        }
+    }
+}
+
+// prints to both the screen and the serial interface
+macro_rules! report {
+    ($($arg:tt)*) => {
+        println!($($arg)*);
+        serial_println!($($arg)*);
+    };
+}
+
+/// Called by the entry stubs. Returning resumes the interrupted code with the
+/// (possibly modified) registers in `frame`.
+extern "C" fn exception_dispatch(frame: &mut ExceptionFrame) {
+    match frame.vector {
+        BREAKPOINT | DEBUG | NON_MASKABLE_INTERRUPT => {
+            println!("EXCEPTION: {}\n{:#?}", exception_name(frame.vector), frame.stack_frame);
+        }
+        PAGE_FAULT => {
+            // faults in lazily mapped memory areas are resolved and the
+            // faulting instruction is retried
+            let addr = Cr2::read();
+            let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+            if let Err(err) = memory::vma::handle_page_fault(addr, error_code) {
+                fatal_exception(frame, || {
+                    report!("Accessed Address: {:?}", addr);
+                    report!("Reason: {:?}", err);
+                    report!("Mapping: {:?}", memory::translate(addr));
+                });
+            }
+        }
+        _ => fatal_exception(frame, || {}),
+    }
+}
+
+/// Prints everything known about the exception and panics. `details` can
+/// report vector specific information.
+fn fatal_exception(frame: &ExceptionFrame, details: impl FnOnce()) -> ! {
+    // the interrupted code is never resumed, so its locks can be broken to
+    // make sure the report gets out
+    unsafe {
+        crate::vga_buffer::WRITER.force_unlock();
+        crate::serial::SERIAL1.force_unlock();
+    }
+
+    let name = exception_name(frame.vector);
+    report!("EXCEPTION: {} (vector {})", name, frame.vector);
+    report!("Error Code: {}", DecodedErrorCode { vector: frame.vector, error_code: frame.error_code });
+    details();
+    report!("{:#?}", frame.stack_frame);
+    report!("{:?}", frame.registers);
+    panic!("EXCEPTION: {}", name);
+}
+
+// The CPU pushes an error code for some vectors only; the stubs push a 0 for
+// the others so that all of them share the `ExceptionFrame` layout.
+global_asm!(
+    ".pushsection .text",
+    ".macro EXCEPTION_STUB vector, has_error_code",
+    ".global exception_stub_\\vector",
+    "exception_stub_\\vector:",
+    ".if \\has_error_code == 0",
+    "    push 0",
+    ".endif",
+    "    push \\vector",
+    "    jmp exception_common",
+    ".endm",
+    "EXCEPTION_STUB 0, 0",
+    "EXCEPTION_STUB 1, 0",
+    "EXCEPTION_STUB 2, 0",
+    "EXCEPTION_STUB 3, 0",
+    "EXCEPTION_STUB 4, 0",
+    "EXCEPTION_STUB 5, 0",
+    "EXCEPTION_STUB 6, 0",
+    "EXCEPTION_STUB 7, 0",
+    "EXCEPTION_STUB 8, 1",
+    "EXCEPTION_STUB 10, 1",
+    "EXCEPTION_STUB 11, 1",
+    "EXCEPTION_STUB 12, 1",
+    "EXCEPTION_STUB 13, 1",
+    "EXCEPTION_STUB 14, 1",
+    "EXCEPTION_STUB 16, 0",
+    "EXCEPTION_STUB 17, 1",
+    "EXCEPTION_STUB 18, 0",
+    "EXCEPTION_STUB 19, 0",
+    "EXCEPTION_STUB 20, 0",
+    "EXCEPTION_STUB 21, 1",
+    "EXCEPTION_STUB 28, 0",
+    "EXCEPTION_STUB 29, 1",
+    "EXCEPTION_STUB 30, 1",
+    // the stack is 16 byte aligned again after pushing the 15 registers
+    "exception_common:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call {dispatch}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // drop the vector and the error code
+    "    add rsp, 16",
+    "    iretq",
+    ".popsection",
+    dispatch = sym exception_dispatch,
+);
+
+extern "C" {
+    fn exception_stub_0();
+    fn exception_stub_1();
+    fn exception_stub_2();
+    fn exception_stub_3();
+    fn exception_stub_4();
+    fn exception_stub_5();
+    fn exception_stub_6();
+    fn exception_stub_7();
+    fn exception_stub_8();
+    fn exception_stub_10();
+    fn exception_stub_11();
+    fn exception_stub_12();
+    fn exception_stub_13();
+    fn exception_stub_14();
+    fn exception_stub_16();
+    fn exception_stub_17();
+    fn exception_stub_18();
+    fn exception_stub_19();
+    fn exception_stub_20();
+    fn exception_stub_21();
+    fn exception_stub_28();
+    fn exception_stub_29();
+    fn exception_stub_30();
+}
+
+/// Points all exception entries of the IDT to the entry stubs.
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    fn addr(stub: unsafe extern "C" fn()) -> VirtAddr {
+        VirtAddr::new(stub as usize as u64)
+    }
+
+    // the stubs match the layout the CPU pushes for each vector
+    unsafe {
+        idt.divide_error.set_handler_addr(addr(exception_stub_0));
+        idt.debug.set_handler_addr(addr(exception_stub_1));
+        idt.non_maskable_interrupt.set_handler_addr(addr(exception_stub_2));
+        idt.breakpoint.set_handler_addr(addr(exception_stub_3));
+        idt.overflow.set_handler_addr(addr(exception_stub_4));
+        idt.bound_range_exceeded.set_handler_addr(addr(exception_stub_5));
+        idt.invalid_opcode.set_handler_addr(addr(exception_stub_6));
+        idt.device_not_available.set_handler_addr(addr(exception_stub_7));
+        idt.double_fault.set_handler_addr(addr(exception_stub_8))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss.set_handler_addr(addr(exception_stub_10));
+        idt.segment_not_present.set_handler_addr(addr(exception_stub_11));
+        idt.stack_segment_fault.set_handler_addr(addr(exception_stub_12));
+        idt.general_protection_fault.set_handler_addr(addr(exception_stub_13));
+        idt.page_fault.set_handler_addr(addr(exception_stub_14));
+        idt.x87_floating_point.set_handler_addr(addr(exception_stub_16));
+        idt.alignment_check.set_handler_addr(addr(exception_stub_17));
+        idt.machine_check.set_handler_addr(addr(exception_stub_18));
+        idt.simd_floating_point.set_handler_addr(addr(exception_stub_19));
+        idt.virtualization.set_handler_addr(addr(exception_stub_20));
+        idt.cp_protection_exception.set_handler_addr(addr(exception_stub_21));
+        idt.hv_injection_exception.set_handler_addr(addr(exception_stub_28));
+        idt.vmm_communication_exception.set_handler_addr(addr(exception_stub_29));
+        idt.security_exception.set_handler_addr(addr(exception_stub_30));
+    }
+}
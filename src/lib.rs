@@ -15,6 +15,12 @@ pub mod gdt;
 pub mod memory;
 pub mod allocator;
 pub mod task;
+pub mod acpi;
+pub mod power;
+pub mod time;
+pub mod thread;
+pub mod lock;
+pub mod smp;
 
 use core::panic::PanicInfo;
 
@@ -74,6 +80,7 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init();
     x86_64::instructions::interrupts::enable(); 
 }
 
@@ -0,0 +1,142 @@
+// spinlocks that keep interrupts disabled while they are held
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::Location,
+};
+
+mod irq;
+mod order;
+mod raw;
+
+pub use raw::{RawLock, Spin, Ticket};
+
+/// A spinlock that disables interrupts while it is held, so it can be shared
+/// with interrupt handlers and its holder is never preempted.
+///
+/// Interrupts are enabled again once the last lock of the CPU is unlocked,
+/// if they were enabled when the first one was locked, so guards can be
+/// dropped in any order.
+///
+/// Debug builds panic instead of deadlocking when the holder of a lock locks
+/// it again, and when two locks are locked in the opposite order of an
+/// earlier time. Locks are told apart by their address.
+pub struct IrqSafeMutex<T, R: RawLock = Spin> {
+    raw: R,
+    data: UnsafeCell<T>,
+}
+
+/// An `IrqSafeMutex` that is handed to waiting CPUs in the order they
+/// started waiting.
+pub type TicketMutex<T> = IrqSafeMutex<T, Ticket>;
+
+unsafe impl<T: Send, R: RawLock> Send for IrqSafeMutex<T, R> {}
+unsafe impl<T: Send, R: RawLock> Sync for IrqSafeMutex<T, R> {}
+
+impl<T, R: RawLock> IrqSafeMutex<T, R> {
+    /// The type of the lock can't be inferred, so it has to be spelled out
+    /// unless the mutex goes into a static or a field.
+    pub const fn new(value: T) -> Self {
+        IrqSafeMutex {
+            raw: R::UNLOCKED,
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Disables interrupts and spins until the lock is free.
+    #[track_caller]
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T, R> {
+        let location = Location::caller();
+        irq::disable();
+        order::check(self.id(), location);
+        self.raw.lock();
+        order::push(self.id(), location);
+        IrqSafeMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Locks the lock if it is free. Never deadlocks, so it can be used by
+    /// code that may have interrupted the holder.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T, R>> {
+        irq::disable();
+        if !self.raw.try_lock() {
+            irq::restore();
+            return None;
+        }
+        order::push(self.id(), Location::caller());
+        Some(IrqSafeMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Unlocks the lock without a guard, if it is locked.
+    ///
+    /// This function is unsafe because the holder must never touch the data
+    /// again, e.g. because it was interrupted by a fatal exception. The
+    /// interrupts it disabled stay disabled.
+    pub unsafe fn force_unlock(&self) {
+        if self.raw.is_locked() {
+            order::pop(self.id());
+            self.raw.unlock();
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T, R: RawLock> Drop for IrqSafeMutex<T, R> {
+    fn drop(&mut self) {
+        // another lock may get the address
+        order::forget(self.id());
+    }
+}
+
+/// Unlocks the lock and restores the interrupts when dropped. Can't be sent
+/// to other threads, since the interrupts belong to the CPU.
+pub struct IrqSafeMutexGuard<'a, T, R: RawLock = Spin> {
+    mutex: &'a IrqSafeMutex<T, R>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T, R: RawLock> Deref for IrqSafeMutexGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T, R: RawLock> DerefMut for IrqSafeMutexGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T, R: RawLock> Drop for IrqSafeMutexGuard<'_, T, R> {
+    fn drop(&mut self) {
+        order::pop(self.mutex.id());
+        unsafe { self.mutex.raw.unlock() };
+        irq::restore();
+    }
+}
+
+/// Panics in debug builds if an `IrqSafeMutex` is held, e.g. before
+/// switching to another thread.
+pub fn assert_none_held() {
+    debug_assert_eq!(irq::depth(), 0, "an IrqSafeMutex is held");
+}
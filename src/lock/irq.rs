@@ -0,0 +1,35 @@
+// keeps interrupts disabled while any lock is held
+use crate::smp::{self, MAX_CPUS};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+/// How many locks each processor holds. Only changed by the processor
+/// itself, with interrupts disabled.
+static DEPTH: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+/// Whether interrupts were enabled when the first of them was locked.
+static ENABLED_BEFORE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+pub(super) fn disable() {
+    let enabled = interrupts::are_enabled();
+    interrupts::disable();
+    let cpu = smp::cpu_index();
+    let depth = DEPTH[cpu].fetch_add(1, Ordering::Relaxed);
+    if depth == 0 {
+        ENABLED_BEFORE[cpu].store(enabled, Ordering::Relaxed);
+    } else {
+        debug_assert!(!enabled, "interrupts enabled while an IrqSafeMutex is held");
+    }
+}
+
+pub(super) fn restore() {
+    let cpu = smp::cpu_index();
+    let depth = DEPTH[cpu].fetch_sub(1, Ordering::Relaxed);
+    debug_assert!(depth > 0, "more IrqSafeMutex unlocks than locks");
+    if depth == 1 && ENABLED_BEFORE[cpu].load(Ordering::Relaxed) {
+        interrupts::enable();
+    }
+}
+
+pub(super) fn depth() -> usize {
+    DEPTH[smp::cpu_index()].load(Ordering::Relaxed)
+}
@@ -0,0 +1,181 @@
+// finds deadlocks in debug builds: a lock locked again by its holder, and
+// two locks locked in both orders
+pub(super) use imp::{check, forget, pop, push};
+
+#[cfg(debug_assertions)]
+mod imp {
+    use crate::smp::{self, MAX_CPUS};
+    use core::{
+        panic::Location,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use spin::Mutex;
+    use x86_64::instructions::interrupts;
+
+    /// How many locks can be held at once.
+    const MAX_HELD: usize = 16;
+    /// How many pairs of locks are remembered.
+    const MAX_ORDERS: usize = 128;
+
+    #[derive(Clone, Copy)]
+    struct Held {
+        id: usize,
+        location: &'static Location<'static>,
+    }
+
+    /// `second` was locked while `first` was held.
+    #[derive(Clone, Copy)]
+    struct Order {
+        first: Held,
+        second: Held,
+    }
+
+    struct Checker {
+        /// The locks each processor holds.
+        held: [[Option<Held>; MAX_HELD]; MAX_CPUS],
+        orders: [Option<Order>; MAX_ORDERS],
+    }
+
+    static CHECKER: Mutex<Checker> = Mutex::new(Checker {
+        held: [[None; MAX_HELD]; MAX_CPUS],
+        orders: [None; MAX_ORDERS],
+    });
+
+    /// Set once a deadlock was reported, so that the panic handler can still
+    /// take the locks it needs to print.
+    static REPORTED: AtomicBool = AtomicBool::new(false);
+
+    enum Deadlock {
+        Relock(Held),
+        Inversion(Held, Order),
+    }
+
+    /// Checks that locking the lock `id` can't deadlock and remembers the
+    /// order it's locked in. Called with interrupts disabled.
+    pub fn check(id: usize, location: &'static Location<'static>) {
+        if REPORTED.load(Ordering::Relaxed) {
+            return;
+        }
+        let deadlock = {
+            let mut checker = CHECKER.lock();
+            checker.check(Held { id, location })
+        };
+        let Some(deadlock) = deadlock else {
+            return;
+        };
+        // report once, after unlocking the checker
+        if REPORTED.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        match deadlock {
+            Deadlock::Relock(held) => panic!(
+                "deadlock: lock at {} locked again, it's held since {}",
+                location, held.location
+            ),
+            Deadlock::Inversion(held, order) => panic!(
+                "lock order inversion: lock at {} while holding the lock of {}, \
+                 but they were locked the other way round at {} and {}",
+                location, held.location, order.first.location, order.second.location
+            ),
+        }
+    }
+
+    impl Checker {
+        fn check(&mut self, new: Held) -> Option<Deadlock> {
+            let cpu = smp::cpu_index();
+            for held in self.held[cpu].iter().flatten() {
+                if held.id == new.id {
+                    return Some(Deadlock::Relock(*held));
+                }
+                let inverted = self
+                    .orders
+                    .iter()
+                    .flatten()
+                    .find(|order| order.first.id == new.id && order.second.id == held.id);
+                if let Some(order) = inverted {
+                    return Some(Deadlock::Inversion(*held, *order));
+                }
+            }
+            for index in 0..MAX_HELD {
+                if let Some(held) = self.held[cpu][index] {
+                    self.remember(held, new);
+                }
+            }
+            None
+        }
+
+        fn remember(&mut self, first: Held, second: Held) {
+            let known = self
+                .orders
+                .iter()
+                .flatten()
+                .any(|order| order.first.id == first.id && order.second.id == second.id);
+            if known {
+                return;
+            }
+            // orders that don't fit anymore are not checked
+            if let Some(free) = self.orders.iter_mut().find(|order| order.is_none()) {
+                *free = Some(Order { first, second });
+            }
+        }
+    }
+
+    /// Records that the lock `id` was locked.
+    pub fn push(id: usize, location: &'static Location<'static>) {
+        let mut checker = CHECKER.lock();
+        match checker.held[smp::cpu_index()]
+            .iter_mut()
+            .find(|held| held.is_none())
+        {
+            Some(free) => *free = Some(Held { id, location }),
+            None => {
+                drop(checker);
+                if !REPORTED.swap(true, Ordering::Relaxed) {
+                    panic!("more than {} locks held", MAX_HELD);
+                }
+            }
+        }
+    }
+
+    /// Records that the lock `id` was unlocked.
+    pub fn pop(id: usize) {
+        let mut checker = CHECKER.lock();
+        let held = checker.held[smp::cpu_index()]
+            .iter_mut()
+            .rev()
+            .find(|held| held.map_or(false, |held| held.id == id));
+        if let Some(held) = held {
+            *held = None;
+        }
+    }
+
+    /// Forgets the orders of the lock `id`, which is dropped.
+    pub fn forget(id: usize) {
+        // the other functions are called with interrupts disabled already
+        interrupts::without_interrupts(|| {
+            let mut checker = CHECKER.lock();
+            for order in checker.orders.iter_mut() {
+                if order.map_or(false, |order| order.first.id == id || order.second.id == id) {
+                    *order = None;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(debug_assertions))]
+mod imp {
+    use core::panic::Location;
+
+    #[inline(always)]
+    pub fn check(_id: usize, _location: &'static Location<'static>) {}
+
+    #[inline(always)]
+    pub fn push(_id: usize, _location: &'static Location<'static>) {}
+
+    #[inline(always)]
+    pub fn pop(_id: usize) {}
+
+    #[inline(always)]
+    pub fn forget(_id: usize) {}
+}
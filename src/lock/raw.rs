@@ -0,0 +1,101 @@
+// the locks behind `IrqSafeMutex`
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// A lock without data or guard.
+///
+/// This trait is unsafe because `lock` and a successful `try_lock` must not
+/// return while the lock is locked.
+pub unsafe trait RawLock {
+    const UNLOCKED: Self;
+
+    fn lock(&self);
+
+    fn try_lock(&self) -> bool;
+
+    fn is_locked(&self) -> bool;
+
+    /// This function is unsafe because the lock must be locked, and whoever
+    /// locked it must not use it anymore.
+    unsafe fn unlock(&self);
+}
+
+/// A plain spinlock: whichever waiting CPU sees it free first gets it.
+pub struct Spin {
+    locked: AtomicBool,
+}
+
+unsafe impl RawLock for Spin {
+    const UNLOCKED: Self = Spin {
+        locked: AtomicBool::new(false),
+    };
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            // only read while it's taken, to keep the cache line shared
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A ticket lock: every waiting CPU draws a ticket and gets the lock when it
+/// is served, so no CPU waits forever while others keep taking it.
+pub struct Ticket {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+}
+
+unsafe impl RawLock for Ticket {
+    const UNLOCKED: Self = Ticket {
+        next: AtomicUsize::new(0),
+        serving: AtomicUsize::new(0),
+    };
+
+    fn lock(&self) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+
+    unsafe fn unlock(&self) {
+        // only the holder changes it
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
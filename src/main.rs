@@ -8,16 +8,15 @@ extern crate alloc;
 
 use core::panic::PanicInfo;
 
-use morb_os::allocator::HEAP_SIZE;
+use morb_os::allocator::{HEAP_MAX_SIZE, HEAP_SIZE};
 use morb_os::println;
 use bootloader::{BootInfo, entry_point};
-use morb_os::task::{Task, simple_executor::SimpleExecutor};
 use morb_os::task::keyboard;
 
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
-    use morb_os::{memory::{self, BootInfoFrameAllocator}, allocator};
+    use morb_os::{memory::{self, BitmapFrameAllocator}, allocator};
     use x86_64::VirtAddr;
 
     println!("Booting system up...");
@@ -26,18 +25,61 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
-
-    println!("Memory Available: {:?} KBs", HEAP_SIZE / 1024);
+    memory::init_kernel_memory(mapper, frame_allocator);
+    morb_os::gdt::init_ist_stacks().expect("IST stack allocation failed");
+    morb_os::thread::init().expect("thread initialization failed");
+    match morb_os::acpi::init() {
+        Ok(tables) => println!(
+            "ACPI {}: {} CPUs, {} I/O APICs",
+            tables.revision,
+            tables.madt.as_ref().map_or(0, |madt| madt.processors.len()),
+            tables.madt.as_ref().map_or(0, |madt| madt.io_apics.len()),
+        ),
+        Err(err) => println!("ACPI tables unavailable ({:?})", err),
+    }
+    morb_os::time::init_wall_clock();
+    match morb_os::time::tsc::init() {
+        Ok(frequency) => println!("TSC: {} MHz", frequency / 1_000_000),
+        Err(err) => println!("TSC unusable ({:?}), using timer ticks", err),
+    }
+    if let Err(err) = morb_os::interrupts::apic::init() {
+        println!("APIC unavailable ({:?}), using the 8259 PIC", err);
+    }
+    match morb_os::smp::init() {
+        Ok(cpus) => println!("SMP: {} CPUs online", cpus),
+        Err(err) => println!("SMP unavailable ({:?}), running on one CPU", err),
+    }
+
+    println!("Memory Available: {:?} KBs (grows up to {:?} KBs)", HEAP_SIZE / 1024, HEAP_MAX_SIZE / 1024);
 
     #[cfg(test)]
     test_main();
 
-    println!("MorbOS is live!");
+    println!("MorbOS is live! ({} UTC)", morb_os::time::wall_clock());
+
+    run_tasks();
+}
+
+/// Runs the kernel's tasks on the executor picked through the
+/// `simple-executor` feature.
+#[cfg(not(feature = "simple-executor"))]
+fn run_tasks() -> ! {
+    use morb_os::task::executor::Executor;
+
+    let mut executor = Executor::new();
+    executor.spawn(example_task());
+    executor.spawn(keyboard::print_keypresses());
+    executor.run();
+}
+
+#[cfg(feature = "simple-executor")]
+fn run_tasks() -> ! {
+    use morb_os::task::{simple_executor::SimpleExecutor, Task};
 
     let mut executor = SimpleExecutor::new();
     executor.spawn(Task::new(example_task()));
@@ -1,8 +1,18 @@
 use x86_64::{
     VirtAddr,
-    structures::paging::{Page, PhysFrame, Mapper, Size4KiB, FrameAllocator, OffsetPageTable, PageTable},
+    structures::paging::{Page, PhysFrame, Mapper, Size4KiB, FrameAllocator, OffsetPageTable, PageTable, PageTableFlags},
     PhysAddr,
 };
+use x86_64::structures::paging::{mapper::MapToError, PageSize};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::lock::IrqSafeMutex;
+
+pub mod frame_allocator;
+pub mod stack;
+pub mod vma;
+
+pub use frame_allocator::BitmapFrameAllocator;
 
 /// Initialize a new OffsetPageTable.
 ///
@@ -11,10 +21,103 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET
+        .try_init_once(|| physical_memory_offset)
+        .expect("memory::init should only be called once");
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Where the complete physical memory is mapped, set by `init`.
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Returns the address through which `addr` is accessible in the mapping of
+/// the complete physical memory.
+///
+/// Panics if `init` was not called yet.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let physical_memory_offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("memory::init not called");
+    *physical_memory_offset + addr.as_u64()
+}
+
+/// The active page table together with the frame allocator backing it.
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BitmapFrameAllocator,
+}
+
+/// Used by everything that maps memory after boot (e.g. the growing heap).
+/// `None` until `init_kernel_memory` is called.
+///
+/// Nothing may allocate on the heap while holding this lock, since a heap
+/// allocation can lock it to grow the heap.
+pub static KERNEL_MEMORY: IrqSafeMutex<Option<KernelMemory>> = IrqSafeMutex::new(None);
+
+/// Runs `f` with `KERNEL_MEMORY` locked.
+///
+/// Panics if `init_kernel_memory` wasn't called.
+#[track_caller]
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> R {
+    let mut kernel_memory = KERNEL_MEMORY.lock();
+    f(kernel_memory.as_mut().expect("kernel memory not initialized"))
+}
+
+/// Hands the page table and the frame allocator over to `KERNEL_MEMORY`.
+pub fn init_kernel_memory(
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BitmapFrameAllocator,
+) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+    });
+}
+
+/// Device memory is mapped in `MMIO_START..MMIO_END` by `map_mmio`.
+pub const MMIO_START: u64 = 0x_7777_0000_0000;
+pub const MMIO_END: u64 = MMIO_START + 0x_1_0000_0000; // 4 GiB
+
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// Maps the `size` bytes of device memory (e.g. the registers of the APIC) at
+/// `phys_addr` uncached and returns the virtual address of `phys_addr`.
+///
+/// This function is unsafe because the caller must guarantee that the range
+/// belongs to a device and not to memory handed out by the frame allocator.
+pub unsafe fn map_mmio(phys_addr: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame: PhysFrame = PhysFrame::containing_address(phys_addr);
+    let last_frame = PhysFrame::containing_address(phys_addr + size.max(1) - 1u64);
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let mapped_size = (last_frame - first_frame + 1) * Size4KiB::SIZE;
+
+    let start = NEXT_MMIO
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(mapped_size).filter(|&end| end <= MMIO_END)
+        })
+        .map_err(|_| MapToError::FrameAllocationFailed)?;
+    let first_page: Page = Page::containing_address(VirtAddr::new(start));
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    with_kernel_memory(|kernel_memory| {
+        for (i, frame) in frames.enumerate() {
+            let page = first_page + i as u64;
+            kernel_memory
+                .mapper
+                .map_to(page, frame, flags, &mut kernel_memory.frame_allocator)?
+                .flush();
+        }
+        Ok::<_, MapToError<Size4KiB>>(())
+    })?;
+
+    Ok(first_page.start_address() + (phys_addr - first_frame.start_address()))
+}
+
 // make private
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable
 {
@@ -29,21 +132,59 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr // unsafe
 }
 
+/// The size of the page an address is mapped through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappedPageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl MappedPageSize {
+    /// The size of the page in bytes.
+    pub fn size(self) -> u64 {
+        match self {
+            MappedPageSize::Size4KiB => 4096,
+            MappedPageSize::Size2MiB => 2 * 1024 * 1024,
+            MappedPageSize::Size1GiB => 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Where and how a virtual address is mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub phys_addr: PhysAddr,
+    pub page_size: MappedPageSize,
+    /// The flags of the page table entry that maps the page, with the
+    /// permissions of all levels: `WRITABLE` and `USER_ACCESSIBLE` only if
+    /// every level allows it, `NO_EXECUTE` if any level sets it.
+    pub flags: PageTableFlags,
+}
+
+/// Translates the given virtual address through the active page table.
+///
+/// Returns `None` if the address is not mapped or `init` was not called yet.
+pub fn translate(addr: VirtAddr) -> Option<Translation> {
+    let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET.try_get().ok()?;
+    translate_addr_inner(addr, physical_memory_offset)
+}
+
 pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr)
     -> Option<PhysAddr>
 {
-    translate_addr_inner(addr, physical_memory_offset)
+    translate_addr_inner(addr, physical_memory_offset).map(|translation| translation.phys_addr)
 }
 
-/// Private function that is called by `translate_addr`.
+/// Private function that is called by `translate_addr` and `translate`.
 ///
 /// This function is safe to limit the scope of `unsafe` because Rust treats
 /// the whole body of unsafe functions as an unsafe block. This function must
-/// only be reachable through `unsafe fn` from outside of this module.
+/// only be reachable through `unsafe fn` from outside of this module, or with
+/// the offset that was passed to `init`.
 fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
-    -> Option<PhysAddr>
+    -> Option<Translation>
 {
-    use x86_64::structures::paging::page_table::FrameError;
     use x86_64::registers::control::Cr3;
 
     // read the active level 4 frame from the CR3 register
@@ -52,26 +193,50 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
     let table_indexes = [
         addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()
     ];
-    let mut frame = level_4_table_frame;
+    let mut table_addr = level_4_table_frame.start_address();
+    let mut allowed = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut no_execute = PageTableFlags::empty();
 
     // traverse the multi-level page table
-    for &index in &table_indexes {
+    for (level, &index) in table_indexes.iter().enumerate() {
         // convert the frame into a page table reference
-        let virt = physical_memory_offset + frame.start_address().as_u64();
+        let virt = physical_memory_offset + table_addr.as_u64();
         let table_ptr: *const PageTable = virt.as_ptr();
         let table = unsafe {&*table_ptr};
 
-        // read the page table entry and update `frame`
+        // read the page table entry
         let entry = &table[index];
-        frame = match entry.frame() {
-            Ok(frame) => frame,
-            Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        allowed &= flags;
+        no_execute |= flags & PageTableFlags::NO_EXECUTE;
+
+        // the walk ends at a huge page in the level 3 or 2 table, or at the
+        // level 1 table (where the HUGE_PAGE bit means something else)
+        let page_size = match level {
+            1 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size1GiB,
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => MappedPageSize::Size2MiB,
+            3 => MappedPageSize::Size4KiB,
+            _ => {
+                table_addr = entry.addr();
+                continue;
+            }
         };
+
+        // calculate the physical address by adding the offset within the page
+        let offset = addr.as_u64() & (page_size.size() - 1);
+        return Some(Translation {
+            phys_addr: entry.addr().align_down(page_size.size()) + offset,
+            page_size,
+            flags: flags.difference(PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE)
+                | allowed
+                | no_execute,
+        });
     }
 
-    // calculate the physical address by adding the page offset
-    Some(frame.start_address() + u64::from(addr.page_offset()))
+    unreachable!("the level 1 entry always ends the walk");
 }
 
 /// Creates an example mapping for the given page to frame `0xb8000`.
@@ -91,47 +256,3 @@ pub fn create_example_mapping(
     };
     map_to_result.expect("map_to failed").flush();
 }
-
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
-}
-
-impl BootInfoFrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
-    ///
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
-
-    // finds usable frames in the memory
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions
-            .filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions
-            .map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
-}
-
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
-}
\ No newline at end of file
@@ -0,0 +1,218 @@
+// tracks every usable physical frame in a bitmap and keeps the free ones in a
+// doubly linked list that lives inside the free frames themselves
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// The number of 4 KiB frames that make up a 2 MiB frame.
+const FRAMES_PER_HUGE_FRAME: u64 = Size2MiB::SIZE / Size4KiB::SIZE;
+
+/// Written to the start of every free frame to link it into the free list.
+struct FreeFrame {
+    prev: Option<PhysFrame>,
+    next: Option<PhysFrame>,
+}
+
+/// A FrameAllocator that hands out and takes back the usable frames of the
+/// bootloader's memory map in O(1).
+///
+/// 2 MiB frames are found by scanning the bitmap for 512 aligned free frames.
+pub struct BitmapFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    /// One bit per frame number, set if the frame is free.
+    bitmap: &'static mut [u64],
+    free_list: Option<PhysFrame>,
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a FrameAllocator from the passed memory map.
+    ///
+    /// The bitmap is placed in the first usable region large enough for it.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid and that the complete physical memory is mapped at
+    /// `physical_memory_offset`. The main requirement is that all frames that
+    /// are marked as `USABLE` in it are really unused.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // one bit for every frame up to the end of the last usable region
+        let frame_count = usable_regions()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0);
+        let bitmap_words = ((frame_count + 63) / 64) as usize;
+        let bitmap_size = (bitmap_words * 8) as u64;
+
+        let bitmap_region = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_size)
+            .expect("no usable region large enough for the frame bitmap");
+        let bitmap_start = bitmap_region.range.start_addr();
+        let bitmap_frames = bitmap_region.range.start_frame_number
+            ..(bitmap_start + bitmap_size + Size4KiB::SIZE - 1) / Size4KiB::SIZE;
+
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, bitmap_words);
+        bitmap.fill(0);
+
+        let mut allocator = BitmapFrameAllocator {
+            physical_memory_offset,
+            bitmap,
+            free_list: None,
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for region in usable_regions() {
+            let frame_numbers = region.range.start_frame_number..region.range.end_frame_number;
+            for frame_number in frame_numbers.filter(|n| !bitmap_frames.contains(n)) {
+                allocator.push_free(Self::frame(frame_number));
+                allocator.total_frames += 1;
+            }
+        }
+        allocator
+    }
+
+    /// The number of usable frames, including the allocated ones.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// The number of frames that are currently allocated.
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// The number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Allocates a free frame that ends below `limit`, e.g. one that real
+    /// mode code can reach. Scans the bitmap, so it is slower than
+    /// `allocate_frame`.
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        // frame 0 holds the real mode interrupt vectors
+        let end = (limit.as_u64() / Size4KiB::SIZE).min(self.bitmap.len() as u64 * 64);
+        let frame_number = (1..end).find(|&frame_number| self.is_free(frame_number))?;
+        let frame = Self::frame(frame_number);
+        self.remove_free(frame);
+        Some(frame)
+    }
+
+    fn frame(frame_number: u64) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(frame_number * Size4KiB::SIZE))
+    }
+
+    fn frame_number(frame: PhysFrame) -> u64 {
+        frame.start_address().as_u64() / Size4KiB::SIZE
+    }
+
+    fn is_free(&self, frame_number: u64) -> bool {
+        let word = self.bitmap[(frame_number / 64) as usize];
+        word & (1 << (frame_number % 64)) != 0
+    }
+
+    fn set_free(&mut self, frame_number: u64, free: bool) {
+        let word = &mut self.bitmap[(frame_number / 64) as usize];
+        if free {
+            *word |= 1 << (frame_number % 64);
+        } else {
+            *word &= !(1 << (frame_number % 64));
+        }
+    }
+
+    /// Returns a pointer to the list node stored in the given free frame.
+    fn node(&self, frame: PhysFrame) -> *mut FreeFrame {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Marks the given frame as free and puts it at the front of the free list.
+    fn push_free(&mut self, frame: PhysFrame) {
+        let frame_number = Self::frame_number(frame);
+        assert!(!self.is_free(frame_number), "frame {:?} freed twice", frame);
+
+        let node = FreeFrame {
+            prev: None,
+            next: self.free_list,
+        };
+        // the frame is unused, so we are free to store the node in it
+        unsafe {
+            if let Some(head) = self.free_list {
+                (*self.node(head)).prev = Some(frame);
+            }
+            self.node(frame).write(node);
+        }
+        self.free_list = Some(frame);
+        self.set_free(frame_number, true);
+        self.free_frames += 1;
+    }
+
+    /// Marks the given free frame as used and unlinks it from the free list.
+    fn remove_free(&mut self, frame: PhysFrame) {
+        let node = unsafe { self.node(frame).read() };
+        unsafe {
+            match node.prev {
+                Some(prev) => (*self.node(prev)).next = node.next,
+                None => self.free_list = node.next,
+            }
+            if let Some(next) = node.next {
+                (*self.node(next)).prev = node.prev;
+            }
+        }
+        self.set_free(Self::frame_number(frame), false);
+        self.free_frames -= 1;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.free_list?;
+        self.remove_free(frame);
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.push_free(frame);
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        // every 8 bitmap words cover one 2 MiB aligned frame
+        let words_per_huge_frame = (FRAMES_PER_HUGE_FRAME / 64) as usize;
+        let chunk = self
+            .bitmap
+            .chunks_exact(words_per_huge_frame)
+            .position(|words| words.iter().all(|&word| word == u64::MAX))?;
+
+        let first_frame_number = chunk as u64 * FRAMES_PER_HUGE_FRAME;
+        for frame_number in first_frame_number..first_frame_number + FRAMES_PER_HUGE_FRAME {
+            self.remove_free(Self::frame(frame_number));
+        }
+        let start_address = PhysAddr::new(first_frame_number * Size4KiB::SIZE);
+        Some(PhysFrame::containing_address(start_address))
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let first_frame_number = frame.start_address().as_u64() / Size4KiB::SIZE;
+        for frame_number in first_frame_number..first_frame_number + FRAMES_PER_HUGE_FRAME {
+            self.push_free(Self::frame(frame_number));
+        }
+    }
+}
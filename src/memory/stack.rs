@@ -0,0 +1,136 @@
+// kernel stacks allocated at runtime, each with an unmapped guard page below
+// it so that an overflow page faults instead of corrupting other memory
+use super::{vma::{self, ReserveError, VmAreaKind}, with_kernel_memory};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+/// Kernel stacks are placed in `KERNEL_STACKS_START..KERNEL_STACKS_END`.
+pub const KERNEL_STACKS_START: u64 = 0x_6666_0000_0000;
+pub const KERNEL_STACKS_END: u64 = KERNEL_STACKS_START + 0x_1_0000_0000; // 4 GiB
+
+/// The default stack size, in pages.
+pub const DEFAULT_STACK_PAGES: u64 = 5;
+
+/// Where the next stack is placed. Virtual ranges of freed stacks are not
+/// reused, which is fine given the size of the range.
+static NEXT_STACK: AtomicU64 = AtomicU64::new(KERNEL_STACKS_START);
+
+#[derive(Debug)]
+pub enum StackError {
+    /// The virtual range for kernel stacks is used up.
+    OutOfAddressSpace,
+    Reserve(ReserveError),
+    Map(MapToError<Size4KiB>),
+}
+
+/// A mapped kernel stack with a guard page directly below `bottom`.
+#[derive(Debug)]
+pub struct KernelStack {
+    bottom: VirtAddr,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// The lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// The address the stack pointer starts at; stacks grow downwards.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// The usable size of the stack in bytes.
+    pub fn size(&self) -> u64 {
+        self.top - self.bottom
+    }
+
+    /// The guard page directly below the stack.
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(self.bottom - 1u64)
+    }
+}
+
+/// Allocates and maps a stack of `pages` pages with a guard page below it.
+///
+/// The stack is mapped right away instead of lazily, so that it can be used
+/// by interrupt handlers (including the page fault handler) as well.
+pub fn alloc_stack(pages: u64) -> Result<KernelStack, StackError> {
+    let page_size = Size4KiB::SIZE;
+    // one extra page for the guard page
+    let range_size = (pages + 1) * page_size;
+    let guard_start = NEXT_STACK
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(range_size).filter(|&end| end <= KERNEL_STACKS_END)
+        })
+        .map_err(|_| StackError::OutOfAddressSpace)?;
+
+    let guard_start = VirtAddr::new(guard_start);
+    let stack = KernelStack {
+        bottom: guard_start + page_size,
+        top: guard_start + range_size,
+    };
+
+    // reserve the areas before locking the page table; reserving allocates
+    // on the heap, which may need to lock it
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vma::reserve(guard_start, page_size, VmAreaKind::Guard, PageTableFlags::empty())
+        .map_err(StackError::Reserve)?;
+    let area = match vma::reserve(stack.bottom, stack.size(), VmAreaKind::Anonymous, flags) {
+        Ok(area) => area,
+        Err(err) => {
+            unsafe { vma::release(guard_start) };
+            return Err(StackError::Reserve(err));
+        }
+    };
+
+    if let Err(err) = map_stack(&stack, area.flags) {
+        // releasing the areas unmaps whatever was mapped already
+        unsafe { free_stack(stack) };
+        return Err(StackError::Map(err));
+    }
+    Ok(stack)
+}
+
+fn map_stack(stack: &KernelStack, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = Page::range(
+        Page::containing_address(stack.bottom),
+        Page::containing_address(stack.top),
+    );
+    with_kernel_memory(|kernel_memory| {
+        for page in page_range {
+            let frame = kernel_memory
+                .frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let map_result = unsafe {
+                kernel_memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut kernel_memory.frame_allocator)
+            };
+            match map_result {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Unmaps the given stack and frees its frames.
+///
+/// This function is unsafe because the caller must guarantee that the stack
+/// is not in use anymore.
+pub unsafe fn free_stack(stack: KernelStack) {
+    vma::release(stack.bottom);
+    vma::release(stack.guard_page().start_address());
+}
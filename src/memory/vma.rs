@@ -0,0 +1,203 @@
+// kernel virtual memory areas: regions of the address space that are reserved
+// up front and backed by frames lazily from the page fault handler
+use super::{with_kernel_memory, KERNEL_MEMORY};
+use crate::lock::IrqSafeMutex;
+use alloc::collections::BTreeMap;
+use x86_64::{
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize,
+            PageTableFlags, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmAreaKind {
+    /// Zero-filled memory that gets a frame on its first access.
+    Anonymous,
+    /// Memory that must never be accessed, e.g. below a stack.
+    Guard,
+}
+
+/// A reserved region of the kernel's virtual address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmArea {
+    pub start: VirtAddr,
+    /// The first address after the area.
+    pub end: VirtAddr,
+    pub kind: VmAreaKind,
+    /// The flags the pages of the area are mapped with.
+    pub flags: PageTableFlags,
+}
+
+impl VmArea {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReserveError {
+    /// The start or the size is not page aligned, or the size is zero.
+    NotPageAligned,
+    /// The region overlaps an already reserved area.
+    Overlap(VmArea),
+}
+
+/// Why a page fault could not be resolved.
+#[derive(Debug)]
+pub enum PageFaultError {
+    /// No area is reserved at the faulting address.
+    NotReserved,
+    /// The faulting address is inside a guard area.
+    GuardPage(VmArea),
+    /// The page is mapped but the access is not allowed by its flags.
+    ProtectionViolation,
+    /// The access (e.g. a write) is not allowed in the area.
+    AccessDenied(VmArea),
+    /// The fault happened while the area registry or the page table was locked.
+    Busy,
+    /// Mapping a frame for the page failed.
+    MapFailed(MapToError<Size4KiB>),
+}
+
+/// All reserved areas, keyed by their start address.
+static AREAS: IrqSafeMutex<BTreeMap<VirtAddr, VmArea>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// Reserves `size` bytes at `start` without mapping anything yet.
+///
+/// Pages of anonymous areas are mapped with the given flags on their first
+/// access, `PRESENT` is added automatically.
+pub fn reserve(
+    start: VirtAddr,
+    size: u64,
+    kind: VmAreaKind,
+    flags: PageTableFlags,
+) -> Result<VmArea, ReserveError> {
+    if size == 0 || !start.is_aligned(Size4KiB::SIZE) || size % Size4KiB::SIZE != 0 {
+        return Err(ReserveError::NotPageAligned);
+    }
+    let area = VmArea {
+        start,
+        end: start + size,
+        kind,
+        flags: flags | PageTableFlags::PRESENT,
+    };
+
+    let mut areas = AREAS.lock();
+    // the area before `end` is the only one that can overlap
+    if let Some((_, other)) = areas.range(..area.end).next_back() {
+        if other.end > area.start {
+            return Err(ReserveError::Overlap(*other));
+        }
+    }
+    areas.insert(start, area);
+    Ok(area)
+}
+
+/// Removes the area starting at `start`, unmapping its pages and freeing
+/// their frames.
+///
+/// This function is unsafe because the caller must guarantee that the memory
+/// of the area is not used anymore.
+pub unsafe fn release(start: VirtAddr) -> Option<VmArea> {
+    let area = AREAS.lock().remove(&start)?;
+
+    if area.kind == VmAreaKind::Anonymous {
+        with_kernel_memory(|kernel_memory| {
+            for page in area.pages() {
+                // pages that were never accessed are not mapped
+                if let Ok((frame, flush)) = kernel_memory.mapper.unmap(page) {
+                    flush.flush();
+                    kernel_memory.frame_allocator.deallocate_frame(frame);
+                }
+            }
+        });
+    }
+    Some(area)
+}
+
+/// Returns the area containing the given address.
+pub fn find(addr: VirtAddr) -> Option<VmArea> {
+    find_in(&AREAS.lock(), addr)
+}
+
+fn find_in(areas: &BTreeMap<VirtAddr, VmArea>, addr: VirtAddr) -> Option<VmArea> {
+    areas
+        .range(..=addr)
+        .next_back()
+        .map(|(_, area)| *area)
+        .filter(|area| area.contains(addr))
+}
+
+/// Called by the page fault handler for a fault at `addr`.
+///
+/// Maps a zeroed frame if the address belongs to an anonymous area, so the
+/// faulting instruction can be resumed.
+pub fn handle_page_fault(
+    addr: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> Result<(), PageFaultError> {
+    // the page is present, so the fault is about missing access rights
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    // the interrupted code may hold the locks, so waiting would deadlock
+    let area = {
+        let areas = AREAS.try_lock().ok_or(PageFaultError::Busy)?;
+        find_in(&areas, addr).ok_or(PageFaultError::NotReserved)?
+    };
+
+    match area.kind {
+        VmAreaKind::Guard => return Err(PageFaultError::GuardPage(area)),
+        VmAreaKind::Anonymous => {}
+    }
+    let write_denied = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !area.flags.contains(PageTableFlags::WRITABLE);
+    let execute_denied = error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+        && area.flags.contains(PageTableFlags::NO_EXECUTE);
+    if write_denied || execute_denied {
+        return Err(PageFaultError::AccessDenied(area));
+    }
+
+    let mut kernel_memory = KERNEL_MEMORY.try_lock().ok_or(PageFaultError::Busy)?;
+    let kernel_memory = kernel_memory.as_mut().ok_or(PageFaultError::Busy)?;
+    let frame = kernel_memory
+        .frame_allocator
+        .allocate_frame()
+        .ok_or(PageFaultError::MapFailed(MapToError::FrameAllocationFailed))?;
+
+    // anonymous memory starts out zeroed; write through the physical memory
+    // mapping since the page itself isn't mapped yet
+    let frame_ptr: *mut u8 =
+        (kernel_memory.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+    let page = Page::containing_address(addr);
+    let map_result = unsafe {
+        kernel_memory
+            .mapper
+            .map_to(page, frame, area.flags, &mut kernel_memory.frame_allocator)
+    };
+    match map_result {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(err) => {
+            unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+            Err(PageFaultError::MapFailed(err))
+        }
+    }
+}
@@ -0,0 +1,237 @@
+// powering off and restarting the machine
+use crate::{
+    acpi::{self, AddressSpace, Fadt, GenericAddress},
+    memory,
+};
+use core::{convert::Infallible, ptr};
+use x86_64::{
+    instructions::{self, port::Port},
+    structures::DescriptorTablePointer,
+    PhysAddr, VirtAddr,
+};
+
+// PM1 control register bits
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+
+// AML opcodes needed to find the `\_S5` package
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    /// `acpi::init` didn't succeed or there is no FADT.
+    NoFadt,
+    /// The DSDT has no `\_S5` object.
+    NoS5,
+    /// The FADT has no PM1a control block.
+    NoPm1aControlBlock,
+    /// The firmware didn't switch to ACPI mode.
+    AcpiModeTimeout,
+    /// The machine was still running after entering S5.
+    StillRunning,
+}
+
+/// Powers off the machine by entering the ACPI S5 (soft off) sleep state.
+///
+/// Only returns if that failed.
+pub fn shutdown() -> Result<Infallible, PowerError> {
+    let fadt = acpi::tables()
+        .and_then(|tables| tables.fadt.as_ref())
+        .ok_or(PowerError::NoFadt)?;
+    if fadt.pm1a_control_block == 0 {
+        return Err(PowerError::NoPm1aControlBlock);
+    }
+    let (slp_typ_a, slp_typ_b) = acpi::dsdt()
+        .and_then(find_s5)
+        .ok_or(PowerError::NoS5)?;
+
+    // the caller's interrupt state is restored if the machine keeps running
+    instructions::interrupts::without_interrupts(|| {
+        enable_acpi_mode(fadt)?;
+
+        unsafe {
+            write_sleep_type(fadt.pm1a_control_block, slp_typ_a);
+            if fadt.pm1b_control_block != 0 {
+                write_sleep_type(fadt.pm1b_control_block, slp_typ_b);
+            }
+        }
+
+        // entering S5 may take a moment
+        spin_delay();
+        Err(PowerError::StillRunning)
+    })
+}
+
+/// Restarts the machine, trying the ACPI reset register, a pulse of the 8042
+/// reset line and finally a triple fault.
+pub fn reboot() -> ! {
+    instructions::interrupts::disable();
+
+    let fadt = acpi::tables().and_then(|tables| tables.fadt.as_ref());
+    if let Some(fadt) = fadt {
+        if let Some(reset_register) = fadt.reset_register {
+            if unsafe { write_reset_register(reset_register, fadt.reset_value) } {
+                spin_delay();
+            }
+        }
+    }
+
+    if fadt.map_or(true, |fadt| fadt.has_8042()) {
+        unsafe { pulse_8042_reset() };
+        spin_delay();
+    }
+
+    // an exception without an IDT escalates to a triple fault, which resets
+    // the CPU
+    unsafe {
+        instructions::tables::lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+    }
+    instructions::interrupts::int3();
+    crate::hlt_loop();
+}
+
+/// Switches from legacy (SMM) mode to ACPI mode if the firmware is not in
+/// ACPI mode yet.
+fn enable_acpi_mode(fadt: &Fadt) -> Result<(), PowerError> {
+    let mut pm1a_control: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    if fadt.smi_command_port == 0 || unsafe { pm1a_control.read() } & SCI_EN != 0 {
+        return Ok(());
+    }
+
+    let mut smi_command: Port<u8> = Port::new(fadt.smi_command_port as u16);
+    unsafe { smi_command.write(fadt.acpi_enable) };
+    for _ in 0..1_000_000 {
+        if unsafe { pm1a_control.read() } & SCI_EN != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(PowerError::AcpiModeTimeout)
+}
+
+unsafe fn write_sleep_type(control_block: u32, sleep_type: u8) {
+    let mut port: Port<u16> = Port::new(control_block as u16);
+    let value = port.read() & !SLP_TYP_MASK;
+    port.write(value | ((sleep_type as u16) << SLP_TYP_SHIFT) | SLP_EN);
+}
+
+/// Returns whether the register could be written.
+unsafe fn write_reset_register(register: GenericAddress, value: u8) -> bool {
+    match register.address_space {
+        AddressSpace::SystemIo => Port::new(register.address as u16).write(value),
+        AddressSpace::SystemMemory => {
+            let Ok(addr) = memory::map_mmio(PhysAddr::new(register.address), 1) else {
+                return false;
+            };
+            ptr::write_volatile(addr.as_mut_ptr::<u8>(), value);
+        }
+        AddressSpace::PciConfig => {
+            // the address encodes device, function and offset on bus 0
+            let device = (register.address >> 32) & 0x1f;
+            let function = (register.address >> 16) & 0x7;
+            let offset = register.address & 0xff;
+            let config_address = 0x8000_0000 | (device << 11) | (function << 8) | (offset & 0xfc);
+            Port::<u32>::new(0xcf8).write(config_address as u32);
+            Port::<u8>::new(0xcfc + (offset as u16 & 0b11)).write(value);
+        }
+        AddressSpace::Other(_) => return false,
+    }
+    true
+}
+
+/// Lets the keyboard controller pulse the CPU reset line.
+unsafe fn pulse_8042_reset() {
+    let mut status: Port<u8> = Port::new(0x64);
+    // wait until the controller accepts commands
+    for _ in 0..100_000 {
+        if status.read() & 0b10 == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    status.write(0xfe);
+}
+
+fn spin_delay() {
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Finds the `\_S5` package in the AML of a definition block and returns its
+/// SLP_TYPa and SLP_TYPb values.
+fn find_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    // the name must be declared by a NameOp, possibly as the root path
+    // `\_S5_`; it may also appear in references or data before that
+    let declared = |&position: &usize| match position {
+        0 => false,
+        _ if aml[position - 1] == AML_NAME_OP => true,
+        1 => false,
+        _ => aml[position - 1] == b'\\' && aml[position - 2] == AML_NAME_OP,
+    };
+    let position = aml
+        .windows(4)
+        .enumerate()
+        .filter(|(_, name)| *name == b"_S5_")
+        .map(|(position, _)| position)
+        .find(declared)?;
+    let package = aml.get(position + 4..)?;
+    if *package.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+
+    // skip the PkgLength, whose first byte tells how many bytes follow it,
+    // and the element count
+    let pkg_length_bytes = 1 + (*package.get(1)? >> 6) as usize;
+    let mut elements = package.get(1 + pkg_length_bytes + 1..)?;
+    let mut next_integer = || {
+        let (value, length) = match *elements.first()? {
+            AML_ZERO_OP => (0, 1),
+            AML_ONE_OP => (1, 1),
+            AML_BYTE_PREFIX => (*elements.get(1)?, 2),
+            _ => return None,
+        };
+        elements = &elements[length..];
+        Some(value)
+    };
+    let slp_typ_a = next_integer()?;
+    let slp_typ_b = next_integer()?;
+    Some((slp_typ_a, slp_typ_b))
+}
+
+#[test_case]
+fn test_find_s5() {
+    // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+    let aml = [
+        0x10, 0x0c, b'_', b'S', b'4', b'_', // unrelated bytes
+        AML_NAME_OP, b'\\', b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x0a, 0x04,
+        AML_BYTE_PREFIX, 0x05, AML_ZERO_OP, AML_ZERO_OP, AML_ZERO_OP,
+    ];
+    assert_eq!(find_s5(&aml), Some((5, 0)));
+
+    // Name (_S5, Package (0x02) { One, 0x07 })
+    let aml = [
+        AML_NAME_OP, b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x06, 0x02,
+        AML_ONE_OP, AML_BYTE_PREFIX, 0x07,
+    ];
+    assert_eq!(find_s5(&aml), Some((1, 7)));
+
+    // a reference to \_S5 before its declaration
+    let aml = [
+        b'\\', b'_', b'S', b'5', b'_', AML_ZERO_OP,
+        AML_NAME_OP, b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x06, 0x02,
+        AML_ONE_OP, AML_BYTE_PREFIX, 0x07,
+    ];
+    assert_eq!(find_s5(&aml), Some((1, 7)));
+
+    assert_eq!(find_s5(b"no sleep states"), None);
+}
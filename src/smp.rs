@@ -0,0 +1,265 @@
+// symmetric multiprocessing: starts the application processors listed in
+// the MADT; for now they only count their timer ticks while the bootstrap
+// processor runs the threads and tasks
+use crate::{
+    acpi, gdt,
+    interrupts::{self, apic},
+    memory::stack::{self, StackError},
+    thread,
+    time::pit,
+};
+use conquer_once::spin::OnceCell;
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::{registers::model_specific::GsBase, VirtAddr};
+
+mod trampoline;
+
+use trampoline::Trampoline;
+pub use trampoline::TrampolineError;
+
+/// Processors beyond this many are left off.
+pub const MAX_CPUS: usize = 16;
+
+/// How long a processor gets to come online after its startup IPIs.
+const STARTUP_TIMEOUT_MICROS: u32 = 100_000;
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// `acpi::init` found no MADT, so the processors are unknown.
+    NoMadt,
+    /// `apic::init` didn't succeed.
+    ApicDisabled,
+    Trampoline(TrampolineError),
+}
+
+/// The state of a processor. The GS base of each processor points to its
+/// own, so `index` is at `gs:0`.
+#[repr(C)]
+struct Cpu {
+    index: AtomicUsize,
+    apic_id: AtomicU32,
+    online: AtomicBool,
+    /// Set by the processor when it reaches `ap_entry`, or by the bootstrap
+    /// processor when it gives up on it; whoever sets it first decides.
+    claimed: AtomicBool,
+    /// Timer interrupts of this processor.
+    ticks: AtomicU64,
+    gdt: OnceCell<gdt::ApGdt>,
+}
+
+static CPUS: [Cpu; MAX_CPUS] = [const {
+    Cpu {
+        index: AtomicUsize::new(0),
+        apic_id: AtomicU32::new(0),
+        online: AtomicBool::new(false),
+        claimed: AtomicBool::new(false),
+        ticks: AtomicU64::new(0),
+        gdt: OnceCell::uninit(),
+    }
+}; MAX_CPUS];
+
+/// How many entries of `CPUS` are in use, online or not.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// Set once the GS base of the bootstrap processor points to its `Cpu`.
+/// Before, it is the only processor running.
+static GS_READY: AtomicBool = AtomicBool::new(false);
+
+/// The index of the executing processor; 0 is the bootstrap processor.
+pub fn cpu_index() -> usize {
+    if !GS_READY.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let index: usize;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) index, options(nostack, readonly, preserves_flags));
+    }
+    index
+}
+
+/// Whether the executing processor is the bootstrap processor.
+pub fn is_bsp() -> bool {
+    cpu_index() == 0
+}
+
+/// The number of processors that are running, including the bootstrap
+/// processor.
+pub fn online_cpus() -> usize {
+    let count = CPU_COUNT.load(Ordering::Acquire);
+    CPUS[..count]
+        .iter()
+        .enumerate()
+        .filter(|(index, cpu)| *index == 0 || cpu.online.load(Ordering::Acquire))
+        .count()
+}
+
+/// The local APIC ID of the processor with the given index, if it is online.
+pub fn apic_id(index: usize) -> Option<u32> {
+    let cpu = CPUS.get(index)?;
+    (index == 0 || cpu.online.load(Ordering::Acquire)).then(|| cpu.apic_id.load(Ordering::Relaxed))
+}
+
+/// The timer interrupts the processor with the given index received.
+pub fn ticks(index: usize) -> Option<u64> {
+    CPUS.get(index).map(|cpu| cpu.ticks.load(Ordering::Relaxed))
+}
+
+/// Called by the timer interrupt handler on every processor.
+pub(crate) fn count_tick() {
+    CPUS[cpu_index()].ticks.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Starts the enabled processors of the MADT, one after the other, and
+/// returns how many processors are online.
+///
+/// Must be called on the bootstrap processor after `acpi::init` and
+/// `apic::init`. Processors that don't come online are skipped.
+pub fn init() -> Result<usize, SmpError> {
+    let madt = acpi::tables()
+        .and_then(|tables| tables.madt.as_ref())
+        .ok_or(SmpError::NoMadt)?;
+    let local_apic = apic::local_apic()
+        .filter(|_| apic::is_enabled())
+        .ok_or(SmpError::ApicDisabled)?;
+
+    let bsp_apic_id = local_apic.id();
+    let bsp = &CPUS[0];
+    bsp.apic_id.store(bsp_apic_id as u32, Ordering::Relaxed);
+    bsp.online.store(true, Ordering::Release);
+    GsBase::write(VirtAddr::from_ptr(bsp));
+    GS_READY.store(true, Ordering::Relaxed);
+
+    let trampoline = Trampoline::new().map_err(SmpError::Trampoline)?;
+    let application_processors = madt
+        .processors
+        .iter()
+        .filter(|processor| processor.enabled && processor.apic_id != bsp_apic_id as u32);
+    for processor in application_processors {
+        let index = CPU_COUNT.load(Ordering::Relaxed);
+        if index == MAX_CPUS {
+            crate::println!("SMP: more than {} CPUs, the rest stay offline", MAX_CPUS);
+            break;
+        }
+        // xAPIC mode only reaches 8 bit IDs
+        let Ok(apic_id) = u8::try_from(processor.apic_id) else {
+            crate::println!("SMP: CPU with APIC ID {} unreachable", processor.apic_id);
+            continue;
+        };
+        CPU_COUNT.store(index + 1, Ordering::Release);
+        match start(&trampoline, index, apic_id) {
+            Ok(true) => {}
+            Ok(false) => crate::println!("SMP: CPU with APIC ID {} didn't start", apic_id),
+            Err(err) => {
+                crate::println!("SMP: CPU with APIC ID {} not started ({:?})", apic_id, err);
+            }
+        }
+    }
+    // every processor is either online or parked by `start`, so none runs
+    // the trampoline anymore
+    drop(trampoline);
+    Ok(online_cpus())
+}
+
+/// Starts the processor `apic_id` as `CPUS[index]` with the INIT-SIPI-SIPI
+/// sequence. Returns whether it came online.
+///
+/// A processor that doesn't reach `ap_entry` in time is reset with another
+/// INIT IPI, so that it doesn't run the trampoline once it's prepared for
+/// the next processor or freed. Its stack is freed and its GDT never
+/// allocated.
+fn start(trampoline: &Trampoline, index: usize, apic_id: u8) -> Result<bool, StackError> {
+    let cpu = &CPUS[index];
+    cpu.index.store(index, Ordering::Relaxed);
+    cpu.apic_id.store(apic_id as u32, Ordering::Relaxed);
+    // freed only if the processor doesn't come online
+    let stack = stack::alloc_stack(thread::STACK_PAGES)?;
+    trampoline.prepare(stack.top(), ap_entry, index as u64);
+
+    let local_apic = apic::local_apic().unwrap();
+    unsafe {
+        local_apic.send_init(apic_id);
+        delay(10_000);
+        // the second startup IPI is only needed if the first got lost
+        for _ in 0..2 {
+            if cpu.claimed.load(Ordering::Acquire) {
+                break;
+            }
+            local_apic.send_startup(apic_id, trampoline.vector());
+            delay(200);
+        }
+    }
+
+    let mut waited = 0;
+    while !cpu.claimed.load(Ordering::Acquire) && waited < STARTUP_TIMEOUT_MICROS {
+        delay(1_000);
+        waited += 1_000;
+    }
+    if !cpu.claimed.swap(true, Ordering::AcqRel) {
+        // it may still be in the trampoline, park it again
+        unsafe {
+            local_apic.send_init(apic_id);
+            stack::free_stack(stack);
+        }
+        return Ok(false);
+    }
+
+    // past the trampoline, it's done with the shared data and waits for its
+    // tables in `ap_entry`
+    let gdt = match gdt::ApGdt::new() {
+        Ok(gdt) => gdt,
+        Err(err) => {
+            unsafe {
+                local_apic.send_init(apic_id);
+                stack::free_stack(stack);
+            }
+            return Err(err);
+        }
+    };
+    cpu.gdt.init_once(|| gdt);
+    while !cpu.online.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    Ok(true)
+}
+
+/// Where application processors continue from the trampoline, with
+/// interrupts disabled.
+extern "C" fn ap_entry(index: u64) -> ! {
+    let cpu = &CPUS[index as usize];
+    if cpu.claimed.swap(true, Ordering::AcqRel) {
+        // too late, the bootstrap processor gave up and resets this one
+        loop {
+            x86_64::instructions::hlt();
+        }
+    }
+    // before anything else, since locks look up the processor index
+    GsBase::write(VirtAddr::from_ptr(cpu));
+
+    let gdt = loop {
+        match cpu.gdt.try_get() {
+            Ok(gdt) => break gdt,
+            Err(_) => spin_loop(),
+        }
+    };
+    gdt.load();
+    interrupts::init_idt();
+    apic::init_ap();
+    cpu.online.store(true, Ordering::Release);
+
+    x86_64::instructions::interrupts::enable();
+    crate::hlt_loop();
+}
+
+/// Busy waits for the given number of microseconds, using channel 2 of the
+/// PIT so that it works with interrupts disabled.
+fn delay(micros: u32) {
+    let count = pit::PIT_FREQUENCY_HZ as u64 * micros as u64 / 1_000_000;
+    pit::start_countdown(count.clamp(1, u16::MAX as u64) as u16);
+    while !pit::countdown_finished() {
+        spin_loop();
+    }
+}
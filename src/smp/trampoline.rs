@@ -0,0 +1,214 @@
+// the code application processors start in: it switches from real mode
+// straight to long mode and calls into the kernel
+use crate::memory::{self, with_kernel_memory};
+use core::{arch::global_asm, ptr::addr_of};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr3, Cr4},
+        model_specific::Efer,
+    },
+    structures::paging::{FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+// Copied to a page below 1 MiB, whose number is the vector of the startup
+// IPI. The processor starts at its first byte with `cs` set to the page, so
+// the 16 bit code addresses the data at the start by offset. The page is
+// identity mapped, so that the code keeps running once paging is enabled.
+global_asm!(
+    ".pushsection .text",
+    ".balign 16",
+    ".global smp_trampoline_start",
+    "smp_trampoline_start:",
+    // jmp short to the code at offset 96
+    "    .byte 0xeb, 94",
+    "    .fill 6, 1, 0",
+    // offset 8: a `TrampolineData`
+    "    .fill 7, 8, 0",
+    // offset 64: the GDT, with a 64 bit code segment
+    "    .quad 0",
+    "    .quad 0x00af9a000000ffff",
+    // offset 80: the GDT pointer, its base written by `Trampoline::new`
+    "    .word 15",
+    "    .long 0",
+    "    .word 0",
+    // offset 88: the far pointer to the 64 bit code, its offset written by
+    // `Trampoline::new`
+    "    .long 0",
+    "    .word 8",
+    "    .word 0",
+    // offset 96
+    ".code16",
+    "    cli",
+    "    cld",
+    "    mov ax, cs",
+    "    mov ds, ax",
+    // the control registers and EFER of the bootstrap processor, which
+    // enable long mode together
+    "    mov eax, dword ptr ds:[16]",
+    "    mov cr4, eax",
+    "    mov eax, dword ptr ds:[8]",
+    "    mov cr3, eax",
+    "    mov ecx, 0xc0000080",
+    "    mov eax, dword ptr ds:[24]",
+    "    mov edx, dword ptr ds:[28]",
+    "    wrmsr",
+    "    lgdt ds:[80]",
+    "    mov eax, dword ptr ds:[32]",
+    "    mov cr0, eax",
+    // jmp far dword ptr ds:[88]
+    "    .byte 0x66, 0xff, 0x2e",
+    "    .word 88",
+    ".code64",
+    ".global smp_trampoline_long_mode",
+    "smp_trampoline_long_mode:",
+    "    xor eax, eax",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov ss, ax",
+    "    mov rsp, [rip + smp_trampoline_start + 40]",
+    "    mov rdi, [rip + smp_trampoline_start + 56]",
+    "    mov rax, [rip + smp_trampoline_start + 48]",
+    "    call rax",
+    "    ud2",
+    ".global smp_trampoline_end",
+    "smp_trampoline_end:",
+    ".popsection",
+);
+
+extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_long_mode: u8;
+    static smp_trampoline_end: u8;
+}
+
+// offsets into the trampoline
+const DATA_OFFSET: usize = 8;
+const GDT_OFFSET: usize = 64;
+const GDTR_OFFSET: usize = 80;
+const FAR_JUMP_OFFSET: usize = 88;
+
+/// Read by the trampoline at `DATA_OFFSET`.
+#[repr(C)]
+struct TrampolineData {
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    cr0: u64,
+    stack_top: u64,
+    entry: u64,
+    arg: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrampolineError {
+    /// No free frame below 1 MiB.
+    NoLowFrame,
+    /// The page table is above 4 GiB, out of reach of the 32 bit `cr3`.
+    PageTableTooHigh,
+    /// The page the trampoline has to be identity mapped at is in use.
+    PageInUse,
+}
+
+/// The trampoline copied to its page, ready to start processors.
+pub struct Trampoline {
+    frame: PhysFrame,
+}
+
+/// The offset of a label from the start of the trampoline.
+fn offset_of(label: *const u8) -> usize {
+    label as usize - addr_of!(smp_trampoline_start) as usize
+}
+
+impl Trampoline {
+    /// Copies the trampoline to a frame below 1 MiB and identity maps it.
+    ///
+    /// Must be called after `memory::init_kernel_memory`.
+    pub fn new() -> Result<Trampoline, TrampolineError> {
+        let (cr3_frame, _) = Cr3::read();
+        if cr3_frame.start_address().as_u64() > u32::MAX as u64 {
+            return Err(TrampolineError::PageTableTooHigh);
+        }
+
+        let frame = with_kernel_memory(|kernel_memory| {
+            let frame = kernel_memory
+                .frame_allocator
+                .allocate_frame_below(PhysAddr::new(0x10_0000))
+                .ok_or(TrampolineError::NoLowFrame)?;
+            let page =
+                Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            let mapped = unsafe {
+                kernel_memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut kernel_memory.frame_allocator)
+            };
+            match mapped {
+                Ok(flush) => {
+                    flush.flush();
+                    Ok(frame)
+                }
+                Err(_) => {
+                    unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+                    Err(TrampolineError::PageInUse)
+                }
+            }
+        })?;
+
+        let base = frame.start_address().as_u64();
+        let code = unsafe {
+            let start = addr_of!(smp_trampoline_start);
+            let len = offset_of(addr_of!(smp_trampoline_end));
+            core::slice::from_raw_parts(start, len)
+        };
+        let page: *mut u8 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+        unsafe {
+            page.copy_from_nonoverlapping(code.as_ptr(), code.len());
+            // the absolute addresses depend on where it was copied to
+            let gdt = base as u32 + GDT_OFFSET as u32;
+            (page.add(GDTR_OFFSET + 2) as *mut u32).write_unaligned(gdt);
+            let long_mode = base as u32 + offset_of(addr_of!(smp_trampoline_long_mode)) as u32;
+            (page.add(FAR_JUMP_OFFSET) as *mut u32).write_unaligned(long_mode);
+        }
+        Ok(Trampoline { frame })
+    }
+
+    /// The vector of the startup IPI, i.e. the page number of the trampoline.
+    pub fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() / 4096) as u8
+    }
+
+    /// Makes the next processor started through the trampoline call
+    /// `entry(arg)` on the stack `stack_top`, in the address space and with
+    /// the paging features of the executing processor.
+    pub fn prepare(&self, stack_top: VirtAddr, entry: extern "C" fn(u64) -> !, arg: u64) {
+        let efer = Efer::read_raw();
+        let data = TrampolineData {
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+            // long mode is only active once paging is enabled
+            efer: efer & !(1 << 10),
+            cr0: Cr0::read_raw(),
+            stack_top: stack_top.as_u64(),
+            entry: entry as u64,
+            arg,
+        };
+        let page = memory::phys_to_virt(self.frame.start_address());
+        let data_ptr = page + DATA_OFFSET;
+        unsafe { data_ptr.as_mut_ptr::<TrampolineData>().write_volatile(data) };
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(
+            self.frame.start_address().as_u64(),
+        ));
+        with_kernel_memory(|kernel_memory| {
+            if let Ok((frame, flush)) = kernel_memory.mapper.unmap(page) {
+                flush.flush();
+                unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
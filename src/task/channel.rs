@@ -0,0 +1,9 @@
+// channels to pass messages between tasks, and from interrupt handlers to
+// tasks
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+
+/// The error of a `Sink` whose channel was closed by the other side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
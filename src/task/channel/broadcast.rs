@@ -0,0 +1,240 @@
+// a channel that delivers every message to every receiver; receivers that
+// fall behind by more than the capacity miss the oldest messages
+use super::Closed;
+use crate::{lock::IrqSafeMutex, task::sync::wait_queue::WaitQueue};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_util::{sink::Sink, stream::Stream};
+
+/// The error of `Sender::send` if there is no receiver; holds the value
+/// that wasn't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// A receiver fell behind and missed the given number of messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// All senders were dropped and no message is left.
+    Closed,
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct State<T> {
+    /// The most recent messages, at most `capacity` of them.
+    buffer: VecDeque<T>,
+    capacity: usize,
+    /// The position of the first message in `buffer` among all messages.
+    head: u64,
+    senders: usize,
+    receivers: usize,
+    /// Receivers waiting for the next message.
+    waiters: WaitQueue,
+}
+
+impl<T> State<T> {
+    /// The position the next message is sent at.
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+}
+
+impl<T: Clone> State<T> {
+    /// Takes the message at position `next` for a receiver, if it was sent
+    /// already.
+    fn take(&self, next: &mut u64) -> Option<Result<T, Lagged>> {
+        if *next < self.head {
+            let missed = self.head - *next;
+            *next = self.head;
+            return Some(Err(Lagged(missed)));
+        }
+        let value = self.buffer.get((*next - self.head) as usize)?.clone();
+        *next += 1;
+        Some(Ok(value))
+    }
+}
+
+/// Creates a channel that keeps the last `capacity` messages for receivers
+/// that are behind.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    let state = Arc::new(IrqSafeMutex::new(State {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        head: 0,
+        senders: 1,
+        receivers: 1,
+        waiters: WaitQueue::new(),
+    }));
+    let receiver = Receiver {
+        state: state.clone(),
+        next: 0,
+        key: None,
+    };
+    (Sender { state }, receiver)
+}
+
+/// Sends messages to all receivers; cloned for more producers.
+pub struct Sender<T> {
+    state: Arc<IrqSafeMutex<State<T>>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to the current receivers and returns how many there
+    /// are. Never waits: the oldest message is dropped if the buffer is
+    /// full.
+    ///
+    /// Doesn't allocate, so it can be called by interrupt handlers (dropping
+    /// the oldest message may free memory though).
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut state = self.state.lock();
+        if state.receivers == 0 {
+            return Err(SendError(value));
+        }
+        if state.buffer.len() == state.capacity {
+            state.buffer.pop_front();
+            state.head += 1;
+        }
+        state.buffer.push_back(value);
+        state.waiters.wake_all();
+        Ok(state.receivers)
+    }
+
+    /// Creates a receiver for the messages sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let next = {
+            let mut state = self.state.lock();
+            state.receivers += 1;
+            state.tail()
+        };
+        Receiver {
+            state: self.state.clone(),
+            next,
+            key: None,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.state.lock().senders += 1;
+        Sender {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.waiters.wake_all();
+        }
+    }
+}
+
+impl<T: Clone> Sink<T> for Sender<T> {
+    type Error = Closed;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, value: T) -> Result<(), Closed> {
+        self.send(value).map(|_| ()).map_err(|_| Closed)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub struct Receiver<T> {
+    state: Arc<IrqSafeMutex<State<T>>>,
+    /// The position of the next message to receive.
+    next: u64,
+    /// The key of the parked waiter.
+    key: Option<u64>,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Waits for the next message. Returns `RecvError::Lagged` once if
+    /// messages were missed, and continues with the oldest one kept.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.state.lock();
+        match state.take(&mut self.next) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(Lagged(missed))) => Err(TryRecvError::Lagged(missed)),
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        let mut state = self.state.lock();
+        if let Some(waiting) = self.key {
+            // every message and the last sender wake all waiters
+            if !state.waiters.poll(waiting, cx.waker()) {
+                return Poll::Pending;
+            }
+            self.key = None;
+        }
+        if let Some(result) = state.take(&mut self.next) {
+            return Poll::Ready(result.map_err(|Lagged(missed)| RecvError::Lagged(missed)));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(Err(RecvError::Closed));
+        }
+        self.key = Some(state.waiters.push(cx.waker(), ()));
+        Poll::Pending
+    }
+}
+
+impl<T: Clone> Stream for Receiver<T> {
+    type Item = Result<T, Lagged>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.get_mut().poll_recv(cx) {
+            Poll::Ready(Ok(value)) => Poll::Ready(Some(Ok(value))),
+            Poll::Ready(Err(RecvError::Lagged(missed))) => Poll::Ready(Some(Err(Lagged(missed)))),
+            Poll::Ready(Err(RecvError::Closed)) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.receivers -= 1;
+        if let Some(key) = self.key.take() {
+            state.waiters.remove(key);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+// multi-producer single-consumer channels, bounded or unbounded
+use super::Closed;
+use crate::{lock::IrqSafeMutex, task::sync::wait_queue::WaitQueue};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::{sink::Sink, stream::Stream};
+
+/// The error of `Sender::send` if the receiver was dropped; holds the value
+/// that wasn't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The bounded channel has no room left.
+    Full(T),
+    /// The receiver was dropped.
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// All senders were dropped and no message is left.
+    Closed,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// `None` for unbounded channels.
+    capacity: Option<usize>,
+    /// Slots that senders got ready to send to, counted as full.
+    reserved: usize,
+    senders: usize,
+    receiver_alive: bool,
+    receiver_waker: Option<Waker>,
+    /// Senders waiting for room in a full channel.
+    send_waiters: WaitQueue,
+}
+
+impl<T> State<T> {
+    fn has_room(&self) -> bool {
+        self.capacity
+            .map_or(true, |capacity| self.queue.len() + self.reserved < capacity)
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(waker) = &self.receiver_waker {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Hands a free slot to the sender that waits the longest. The slot is
+    /// reserved for it right away, so that nobody takes it before the
+    /// sender is polled.
+    fn wake_sender(&mut self) {
+        if !self.has_room() {
+            return;
+        }
+        if let Some(waiter) = self.send_waiters.waiting_mut().next() {
+            waiter.wake();
+            self.reserved += 1;
+        }
+    }
+
+    /// Stops waiting for room; passes a slot it was handed on. Senders
+    /// woken because the receiver was dropped weren't handed one.
+    fn cancel_wait(&mut self, key: u64) {
+        if let Some(waiter) = self.send_waiters.remove(key) {
+            if waiter.woken && self.receiver_alive {
+                self.reserved -= 1;
+                self.wake_sender();
+            }
+        }
+    }
+}
+
+struct Channel<T> {
+    state: IrqSafeMutex<State<T>>,
+}
+
+impl<T> Channel<T> {
+    /// Reserves a slot to send to, waiting in line for one if the channel is
+    /// full. `key` is the sender's place in line.
+    fn poll_reserve(&self, key: &mut Option<u64>, cx: &mut Context) -> Poll<Result<(), Closed>> {
+        let mut state = self.state.lock();
+        if !state.receiver_alive {
+            if let Some(key) = key.take() {
+                state.cancel_wait(key);
+            }
+            return Poll::Ready(Err(Closed));
+        }
+        if let Some(waiting) = *key {
+            // woken senders were handed a reserved slot
+            if !state.send_waiters.poll(waiting, cx.waker()) {
+                return Poll::Pending;
+            }
+            *key = None;
+            return Poll::Ready(Ok(()));
+        }
+        // senders that wait already go first
+        if state.has_room() && !state.send_waiters.has_waiting() {
+            state.reserved += 1;
+            Poll::Ready(Ok(()))
+        } else {
+            *key = Some(state.send_waiters.push(cx.waker(), ()));
+            Poll::Pending
+        }
+    }
+
+    /// Sends `value` to a slot reserved by `poll_reserve`, or gives it back
+    /// if the receiver was dropped since.
+    fn send_reserved(&self, value: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+        state.reserved -= 1;
+        if !state.receiver_alive {
+            return Err(value);
+        }
+        state.queue.push_back(value);
+        state.wake_receiver();
+        Ok(())
+    }
+}
+
+fn new<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        state: IrqSafeMutex::new(State {
+            // allocated up front, so that `try_send` doesn't allocate
+            queue: VecDeque::with_capacity(capacity.unwrap_or(0)),
+            capacity,
+            reserved: 0,
+            senders: 1,
+            receiver_alive: true,
+            receiver_waker: None,
+            send_waiters: WaitQueue::new(),
+        }),
+    });
+    let sender = Sender {
+        channel: channel.clone(),
+        reserved: false,
+        waiter_key: None,
+    };
+    (sender, Receiver { channel })
+}
+
+/// Creates a channel that holds up to `capacity` messages; senders wait
+/// while it's full.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    new(Some(capacity))
+}
+
+/// Creates a channel that grows as needed, so sending never waits.
+pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(None)
+}
+
+/// Sends messages to the `Receiver`; cloned for more producers.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+    /// Whether `poll_ready` of the `Sink` reserved a slot.
+    reserved: bool,
+    /// The sender's place in line while `poll_ready` waits for room.
+    waiter_key: Option<u64>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` if there is room, without waiting.
+    ///
+    /// Doesn't allocate for bounded channels, so it can be called by
+    /// interrupt handlers.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.channel.state.lock();
+        if !state.receiver_alive {
+            return Err(TrySendError::Closed(value));
+        }
+        // don't overtake waiting senders
+        if !state.has_room() || state.send_waiters.has_waiting() {
+            return Err(TrySendError::Full(value));
+        }
+        state.queue.push_back(value);
+        state.wake_receiver();
+        Ok(())
+    }
+
+    /// Sends `value`, waiting for room if the channel is full. Senders
+    /// waiting for room send in the order they started waiting.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut waiter = SendWaiter {
+            channel: &self.channel,
+            key: None,
+        };
+        match poll_fn(|cx| waiter.channel.poll_reserve(&mut waiter.key, cx)).await {
+            Ok(()) => self.channel.send_reserved(value).map_err(SendError),
+            Err(Closed) => Err(SendError(value)),
+        }
+    }
+
+    /// Whether the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.channel.state.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().senders += 1;
+        Sender {
+            channel: self.channel.clone(),
+            reserved: false,
+            waiter_key: None,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+        if let Some(key) = self.waiter_key.take() {
+            state.cancel_wait(key);
+        }
+        if self.reserved {
+            state.reserved -= 1;
+            state.wake_sender();
+        }
+        state.senders -= 1;
+        if state.senders == 0 {
+            // lets the receiver see that the channel is closed
+            state.wake_receiver();
+        }
+    }
+}
+
+impl<T> Sink<T> for Sender<T> {
+    type Error = Closed;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Closed>> {
+        let this = self.get_mut();
+        if this.reserved {
+            return Poll::Ready(Ok(()));
+        }
+        let result = this.channel.poll_reserve(&mut this.waiter_key, cx);
+        if let Poll::Ready(Ok(())) = result {
+            this.reserved = true;
+        }
+        result
+    }
+
+    fn start_send(self: Pin<&mut Self>, value: T) -> Result<(), Closed> {
+        let this = self.get_mut();
+        assert!(this.reserved, "start_send called without poll_ready");
+        this.reserved = false;
+        this.channel.send_reserved(value).map_err(|_| Closed)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Closed>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Gives up the place in line of a `send` that is dropped while waiting.
+struct SendWaiter<'a, T> {
+    channel: &'a Channel<T>,
+    key: Option<u64>,
+}
+
+impl<T> Drop for SendWaiter<'_, T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.channel.state.lock().cancel_wait(key);
+        }
+    }
+}
+
+/// Receives the messages of all `Sender`s, in the order they were sent.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next message. Returns `None` once all senders were
+    /// dropped and no message is left.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.channel.state.lock();
+        match state.queue.pop_front() {
+            Some(value) => {
+                state.wake_sender();
+                Ok(value)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.channel.state.lock();
+        if let Some(value) = state.queue.pop_front() {
+            state.wake_sender();
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        match &state.receiver_waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => state.receiver_waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let queue = {
+            let mut state = self.channel.state.lock();
+            state.receiver_alive = false;
+            state.send_waiters.wake_all();
+            core::mem::take(&mut state.queue)
+        };
+        // drop the messages that were never received outside of the lock
+        drop(queue);
+    }
+}
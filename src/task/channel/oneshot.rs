@@ -0,0 +1,123 @@
+// a channel for a single value, e.g. the reply to a request
+use crate::lock::IrqSafeMutex;
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// The error of a `Receiver` whose `Sender` was dropped without sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Canceled,
+}
+
+struct State<T> {
+    value: Option<T>,
+    /// Whether the sender sent or was dropped.
+    sender_done: bool,
+    receiver_alive: bool,
+    receiver_waker: Option<Waker>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(IrqSafeMutex::new(State {
+        value: None,
+        sender_done: false,
+        receiver_alive: true,
+        receiver_waker: None,
+    }));
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+pub struct Sender<T> {
+    state: Arc<IrqSafeMutex<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, or gives it back if the receiver was dropped.
+    ///
+    /// Doesn't allocate, so it can be called by interrupt handlers.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+        if !state.receiver_alive {
+            return Err(value);
+        }
+        state.value = Some(value);
+        // dropping `self` wakes the receiver
+        Ok(())
+    }
+
+    /// Whether the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.state.lock().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.sender_done = true;
+        if let Some(waker) = &state.receiver_waker {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Completes with the sent value, or `Canceled` if the sender was dropped
+/// without sending.
+pub struct Receiver<T> {
+    state: Arc<IrqSafeMutex<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_done => Err(TryRecvError::Canceled),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if state.sender_done {
+            return Poll::Ready(Err(Canceled));
+        }
+        match &state.receiver_waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => state.receiver_waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut state = self.state.lock();
+            state.receiver_alive = false;
+            state.value.take()
+        };
+        // dropped outside of the lock
+        drop(value);
+    }
+}
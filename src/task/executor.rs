@@ -1,57 +1,165 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc};
+use super::{join_handle, JoinHandle, Priority, Task, TaskId};
+use crate::lock::IrqSafeMutex;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::Waker;
-use crossbeam_queue::ArrayQueue;
+use crossbeam_queue::SegQueue;
 use core::task::{Context, Poll};
 
+/// How often a task is polled at most per round. A task that is woken again
+/// after that waits for the next round, so that the other tasks, also those
+/// of lower priority, get their turn.
+const POLL_BUDGET: u32 = 4;
+
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    task_queue: Arc<ReadyQueue>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+    /// Tasks spawned through a `Spawner`, not yet in `tasks`.
+    new_tasks: Arc<SegQueue<SpawnedTask>>,
+    /// Counts the calls of `run_ready_tasks`.
+    round: u64,
+    /// How often the CPU was halted because no task was ready.
+    idle_halts: u64,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            task_queue: Arc::new(ReadyQueue::new()),
             waker_cache: BTreeMap::new(),
+            new_tasks: Arc::new(SegQueue::new()),
+            round: 0,
+            idle_halts: 0,
+        }
+    }
+
+    /// Returns a handle that spawns tasks on this executor while it runs.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            new_tasks: self.new_tasks.clone(),
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
+    /// Spawns `future` as a new task, returning a handle to await its output
+    /// or to abort it.
+    pub fn spawn<F>(&mut self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.spawn_with_priority(future, Priority::default())
+    }
+
+    /// Like `spawn`, but the task is scheduled in the given class.
+    pub fn spawn_with_priority<F>(&mut self, future: F, priority: Priority) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (future, join_handle) = join_handle::joinable(future);
+        self.spawn_task(Task::with_priority(future, priority));
+        join_handle
+    }
+
+    fn spawn_task(&mut self, task: Task) {
+        insert_task(&mut self.tasks, &self.task_queue, task);
+    }
+
+    /// The number of tasks that didn't complete yet.
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Runs the tasks until `future` completes and returns its output.
+    pub fn block_on<F>(&mut self, future: F) -> F::Output
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let mut join_handle = self.spawn(future);
+        loop {
+            self.run_ready_tasks();
+            if let Some(result) = join_handle.try_join() {
+                return result.expect("block_on task aborted");
+            }
+            self.sleep_if_idle();
         }
-        self.task_queue.push(task_id).expect("queue full");
     }
 
+    /// Moves the tasks spawned through a `Spawner` into `tasks`.
+    fn spawn_new_tasks(&mut self) {
+        while let Ok(SpawnedTask(task)) = self.new_tasks.pop() {
+            self.spawn_task(task);
+        }
+    }
+
+    /// Polls the ready tasks, higher priorities first, until none is left or
+    /// all that are left used up their budget for this round.
     fn run_ready_tasks(&mut self) {
+        self.spawn_new_tasks();
+        self.round += 1;
+
         // destructure `self` to avoid borrow checker errors
         let Self {
             tasks,
             task_queue,
             waker_cache,
+            new_tasks,
+            round,
+            ..
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
+        // tasks that used up their budget; they stay scheduled
+        let mut deferred = Vec::new();
+        while let Some(task_id) = task_queue.pop() {
             let task = match tasks.get_mut(&task_id) {
                 Some(task) => task,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
+            if task.round != *round {
+                task.round = *round;
+                task.polls_in_round = 0;
+            }
+            if task.polls_in_round == POLL_BUDGET {
+                deferred.push(task_id);
+                continue;
+            }
+            task.polls_in_round += 1;
+
+            let priority = task.priority;
+            let task_waker = waker_cache
                 .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
+                .or_insert_with(|| TaskWaker::new(task_id, priority, task_queue.clone()));
+            // wakeups from now on need to schedule the task again
+            task_waker.scheduled.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
+                    // task done -> remove it and its cached waker, whose
+                    // clones must not schedule it anymore
+                    task_waker.scheduled.store(true, Ordering::Release);
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
                 }
                 Poll::Pending => {}
             }
+
+            // tasks spawned by the polled task
+            while let Ok(SpawnedTask(task)) = new_tasks.pop() {
+                insert_task(tasks, task_queue, task);
+            }
+        }
+
+        for task_id in deferred {
+            task_queue.push(task_id, tasks[&task_id].priority);
         }
     }
 
@@ -62,35 +170,149 @@ impl Executor {
         }
     }
 
-    fn sleep_if_idle(&self) {
+    /// How often the CPU was halted because no task was ready.
+    pub fn idle_halts(&self) -> u64 {
+        self.idle_halts
+    }
+
+    fn sleep_if_idle(&mut self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
-        } else {
+        if !(self.task_queue.is_empty() && self.new_tasks.is_empty()) {
             interrupts::enable();
+        } else if crate::thread::others_ready() {
+            // let the other threads run instead of halting until the next
+            // interrupt
+            interrupts::enable();
+            crate::thread::yield_now();
+        } else {
+            self.idle_halts += 1;
+            enable_and_hlt();
         }
     }
 }
 
+fn insert_task(tasks: &mut BTreeMap<TaskId, Task>, task_queue: &ReadyQueue, task: Task) {
+    let task_id = task.id;
+    let priority = task.priority;
+    if tasks.insert(task.id, task).is_some() {
+        panic!("task with same ID already in tasks");
+    }
+    task_queue.reserve(tasks.len());
+    task_queue.push(task_id, priority);
+}
+
+/// The IDs of the tasks that are ready to be polled, one FIFO queue per
+/// priority class.
+///
+/// A task is in the queues at most once (see `TaskWaker::scheduled`), so
+/// none holds more IDs than there are tasks. The capacity of each is kept
+/// at the number of tasks, so that pushing never allocates and interrupt
+/// handlers can wake tasks.
+struct ReadyQueue {
+    queues: IrqSafeMutex<[VecDeque<TaskId>; Priority::COUNT]>,
+}
+
+impl ReadyQueue {
+    fn new() -> ReadyQueue {
+        ReadyQueue {
+            queues: IrqSafeMutex::new(Default::default()),
+        }
+    }
+
+    /// Makes room for the IDs of `task_count` tasks in every class.
+    fn reserve(&self, task_count: usize) {
+        for queue in self.queues.lock().iter_mut() {
+            let additional = task_count.saturating_sub(queue.len());
+            queue.reserve(additional);
+        }
+    }
+
+    fn push(&self, task_id: TaskId, priority: Priority) {
+        let queue = &mut self.queues.lock()[priority as usize];
+        debug_assert!(queue.len() < queue.capacity(), "ready queue would allocate");
+        queue.push_back(task_id);
+    }
+
+    /// Takes the first task of the highest priority class that has one.
+    fn pop(&self) -> Option<TaskId> {
+        self.queues
+            .lock()
+            .iter_mut()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.lock().iter().all(|queue| queue.is_empty())
+    }
+}
+
+/// A `Task` that was built from a `Send` future.
+struct SpawnedTask(Task);
+
+// only constructed by `Spawner::spawn`, which requires the future to be `Send`
+unsafe impl Send for SpawnedTask {}
+
+/// Spawns tasks on an `Executor`, also while it runs, e.g. from other tasks
+/// or code deferred from interrupt handlers.
+#[derive(Clone)]
+pub struct Spawner {
+    new_tasks: Arc<SegQueue<SpawnedTask>>,
+}
+
+impl Spawner {
+    /// Spawns `future` as a new task, returning a handle to await its output
+    /// or to abort it. The executor picks it up in its next round.
+    ///
+    /// Allocates, so it must not be called by interrupt handlers directly.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with_priority(future, Priority::default())
+    }
+
+    /// Like `spawn`, but the task is scheduled in the given class.
+    pub fn spawn_with_priority<F>(&self, future: F, priority: Priority) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (future, join_handle) = join_handle::joinable(future);
+        self.new_tasks
+            .push(SpawnedTask(Task::with_priority(future, priority)));
+        join_handle
+    }
+}
+
 use alloc::task::Wake;
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    task_queue: Arc<ReadyQueue>,
+    /// Whether the task is in `task_queue` already (or done), so that
+    /// further wakeups don't push it again.
+    scheduled: AtomicBool,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    /// Creates the waker of a task that was just taken from `task_queue`.
+    fn new(task_id: TaskId, priority: Priority, task_queue: Arc<ReadyQueue>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
             task_id,
+            priority,
             task_queue,
-        }))
+            scheduled: AtomicBool::new(false),
+        })
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.task_queue.push(self.task_id, self.priority);
+        }
     }
 }
 
@@ -0,0 +1,162 @@
+// lets the spawner of a task await its output or abort it
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted before it completed.
+    Aborted,
+}
+
+/// The state shared by a task and its `JoinHandle`.
+struct JoinState<T> {
+    output: Option<T>,
+    /// Whether the task completed or was aborted; its future is dropped then.
+    finished: bool,
+    aborted: bool,
+    /// Woken once the task finished.
+    join_waker: Option<Waker>,
+    /// The waker of the task itself, used to get it polled once aborted.
+    task_waker: Option<Waker>,
+}
+
+/// Owned permission to await the output of a spawned task.
+///
+/// Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task: its future is dropped the next time the executor
+    /// gets to it instead of being polled again. Has no effect if the task
+    /// already completed.
+    pub fn abort(&self) {
+        let task_waker = {
+            let mut state = self.state.lock();
+            if state.finished {
+                return;
+            }
+            state.aborted = true;
+            state.task_waker.take()
+        };
+        if let Some(waker) = task_waker {
+            waker.wake();
+        }
+    }
+
+    /// Whether the task completed or was aborted.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().finished
+    }
+
+    /// The result of the task if it finished, without waiting for it.
+    pub fn try_join(&mut self) -> Option<Result<T, JoinError>> {
+        let mut state = self.state.lock();
+        if let Some(output) = state.output.take() {
+            Some(Ok(output))
+        } else if state.finished && state.aborted {
+            Some(Err(JoinError::Aborted))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        if let Some(output) = state.output.take() {
+            return Poll::Ready(Ok(output));
+        }
+        if state.finished {
+            assert!(state.aborted, "JoinHandle polled after completion");
+            return Poll::Ready(Err(JoinError::Aborted));
+        }
+        state.join_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Wraps `future` into a future that stores its output for the returned
+/// `JoinHandle`.
+pub(super) fn joinable<F>(future: F) -> (Joinable<F>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        finished: false,
+        aborted: false,
+        join_waker: None,
+        task_waker: None,
+    }));
+    let joinable = Joinable {
+        future: Some(future),
+        state: state.clone(),
+    };
+    (joinable, JoinHandle { state })
+}
+
+/// The future of a task with a `JoinHandle`.
+pub(super) struct Joinable<F: Future> {
+    future: Option<F>,
+    state: Arc<Mutex<JoinState<F::Output>>>,
+}
+
+impl<F: Future> Joinable<F> {
+    fn finish(&self, output: Option<F::Output>) {
+        let join_waker = {
+            let mut state = self.state.lock();
+            state.output = output;
+            state.finished = true;
+            state.task_waker = None;
+            state.join_waker.take()
+        };
+        if let Some(waker) = join_waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // `future` is structurally pinned: it's only dropped in place
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        let aborted = {
+            let mut state = this.state.lock();
+            let task_waker = &mut state.task_waker;
+            if !task_waker.as_ref().map_or(false, |waker| waker.will_wake(cx.waker())) {
+                *task_waker = Some(cx.waker().clone());
+            }
+            state.aborted
+        };
+        if aborted {
+            future.set(None);
+            this.finish(None);
+            return Poll::Ready(());
+        }
+
+        let output = match future.as_mut().as_pin_mut() {
+            Some(future) => match future.poll(cx) {
+                Poll::Ready(output) => output,
+                Poll::Pending => return Poll::Pending,
+            },
+            None => return Poll::Ready(()),
+        };
+        future.set(None);
+        this.finish(Some(output));
+        Poll::Ready(())
+    }
+}
@@ -6,20 +6,54 @@ use core::{
     task::{Context, Poll},
 };
 
+pub mod channel;
 pub mod executor;
+mod join_handle;
 pub mod keyboard;
 pub mod simple_executor;
+pub mod sync;
+pub mod timer;
+
+pub use join_handle::{JoinError, JoinHandle};
+
+/// The scheduling class of a task. The executor polls ready tasks of a
+/// higher class first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    /// Work deferred from interrupt handlers.
+    BottomHalf = 0,
+    /// Tasks that react to the user, like the keyboard handler.
+    #[default]
+    Interactive = 1,
+    Background = 2,
+}
+
+impl Priority {
+    pub(crate) const COUNT: usize = 3;
+}
 
 pub struct Task {
     id: TaskId,
+    priority: Priority,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    /// The executor round the task was last polled in and how often it was
+    /// polled in that round.
+    round: u64,
+    polls_in_round: u32,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::with_priority(future, Priority::default())
+    }
+
+    pub fn with_priority(future: impl Future<Output = ()> + 'static, priority: Priority) -> Task {
         Task {
             id: TaskId::new(),
+            priority,
             future: Box::pin(future),
+            round: 0,
+            polls_in_round: 0,
         }
     }
 
@@ -28,6 +62,31 @@ impl Task {
     }
 }
 
+/// Gives up the CPU once: the task is woken again right away, but the
+/// executor polls other ready tasks first.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// The future returned by `yield_now`.
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -1,50 +1,98 @@
 use super::Task;
-use alloc::collections::VecDeque;
-use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use alloc::{collections::VecDeque, sync::Arc, task::Wake};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
 
+/// Polls its tasks round by round, only those that were woken since their
+/// last poll, and halts the CPU while none was.
 pub struct SimpleExecutor {
-    task_queue: VecDeque<Task>,
+    task_queue: VecDeque<(Task, Arc<TaskWaker>)>,
+    /// How often the CPU was halted because no task was woken.
+    idle_halts: u64,
 }
 
 impl SimpleExecutor {
     pub fn new() -> SimpleExecutor {
         SimpleExecutor {
             task_queue: VecDeque::new(),
+            idle_halts: 0,
         }
     }
 
     pub fn spawn(&mut self, task: Task) {
-        self.task_queue.push_back(task)
+        // new tasks are polled right away
+        let waker = Arc::new(TaskWaker {
+            woken: AtomicBool::new(true),
+        });
+        self.task_queue.push_back((task, waker))
     }
 
+    /// Runs the tasks until all of them completed.
     pub fn run(&mut self) {
-        while let Some(mut task) = self.task_queue.pop_front() {
-            let waker = dummy_waker();
-            let mut context = Context::from_waker(&waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {} // task done
-                Poll::Pending => self.task_queue.push_back(task),
+        while !self.task_queue.is_empty() {
+            let mut any_woken = false;
+            for _ in 0..self.task_queue.len() {
+                let (mut task, task_waker) = match self.task_queue.pop_front() {
+                    Some(entry) => entry,
+                    None => break,
+                };
+                if !task_waker.woken.swap(false, Ordering::AcqRel) {
+                    self.task_queue.push_back((task, task_waker));
+                    continue;
+                }
+                any_woken = true;
+
+                let waker = Waker::from(task_waker.clone());
+                let mut context = Context::from_waker(&waker);
+                match task.poll(&mut context) {
+                    Poll::Ready(()) => {} // task done
+                    Poll::Pending => self.task_queue.push_back((task, task_waker)),
+                }
+            }
+            if !any_woken {
+                self.sleep_if_idle();
             }
         }
     }
-}
 
-fn dummy_raw_waker() -> RawWaker {
-    fn no_op(_: *const ()) {}
-    fn clone(_: *const ()) -> RawWaker {
-        dummy_raw_waker()
+    /// How often the CPU was halted because no task was ready.
+    pub fn idle_halts(&self) -> u64 {
+        self.idle_halts
     }
 
-    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
-    RawWaker::new(0 as *const (), vtable)
+    fn sleep_if_idle(&mut self) {
+        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+        // a task woken by an interrupt after the check would be missed
+        // until the next interrupt, so check with interrupts disabled
+        interrupts::disable();
+        let woken = self
+            .task_queue
+            .iter()
+            .any(|(_, task_waker)| task_waker.woken.load(Ordering::Acquire));
+        if woken {
+            interrupts::enable();
+        } else if crate::thread::others_ready() {
+            interrupts::enable();
+            crate::thread::yield_now();
+        } else {
+            self.idle_halts += 1;
+            enable_and_hlt();
+        }
+    }
 }
 
-/*
- * RawWaker --> requires programmer to explicity define a virtual method table (vtable) 
- * that specifies functions that should be called when the RawWaker type is initialized.
- * 
-*/
+/// Marks its task as ready to be polled in the next round.
+struct TaskWaker {
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+    }
 
-fn dummy_waker() -> Waker {
-    unsafe { Waker::from_raw(dummy_raw_waker()) }
-}
\ No newline at end of file
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,16 @@
+// async synchronization primitives for tasks: instead of spinning, waiting
+// tasks are parked with their waker and woken in the order they started
+// waiting, so the primitives can be held across `.await`; their state is
+// behind an `IrqSafeMutex`, since some are signalled by interrupt handlers
+mod event;
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+pub(super) mod wait_queue;
+
+pub use event::{Event, EventWait};
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Acquire, Semaphore, SemaphorePermit};
@@ -0,0 +1,98 @@
+// a flag tasks can wait for; can be set by interrupt handlers
+use super::wait_queue::WaitQueue;
+use crate::lock::IrqSafeMutex;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Stays set until it is reset, waking all waiting tasks when it's set.
+pub struct Event {
+    state: IrqSafeMutex<State>,
+}
+
+struct State {
+    set: bool,
+    waiters: WaitQueue,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            state: IrqSafeMutex::new(State {
+                set: false,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Sets the event and wakes all waiting tasks.
+    ///
+    /// Doesn't allocate, so it can be called by interrupt handlers.
+    pub fn set(&self) {
+        let mut state = self.state.lock();
+        state.set = true;
+        state.waiters.wake_all();
+    }
+
+    /// Clears the event. Tasks woken by the last `set` still complete.
+    pub fn reset(&self) {
+        self.state.lock().set = false;
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.state.lock().set
+    }
+
+    /// Waits until the event is set.
+    pub fn wait(&self) -> EventWait<'_> {
+        EventWait {
+            event: self,
+            key: None,
+        }
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event::new()
+    }
+}
+
+/// The future returned by `Event::wait`.
+pub struct EventWait<'a> {
+    event: &'a Event,
+    key: Option<u64>,
+}
+
+impl Future for EventWait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let mut state = this.event.state.lock();
+        let done = match this.key {
+            None if state.set => true,
+            None => {
+                this.key = Some(state.waiters.push(cx.waker(), ()));
+                false
+            }
+            Some(key) => state.waiters.poll(key, cx.waker()),
+        };
+        if done {
+            this.key = None;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for EventWait<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.event.state.lock().waiters.remove(key);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// wakes waiting tasks without passing data; can be signalled by interrupt
+// handlers
+use super::wait_queue::WaitQueue;
+use crate::lock::IrqSafeMutex;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub struct Notify {
+    state: IrqSafeMutex<State>,
+}
+
+struct State {
+    /// Set by `notify_one` while no task waits, for the next one to wait.
+    permit: bool,
+    /// Whether each waiter was woken by `notify_one`, so that the
+    /// notification is passed on if it stops waiting.
+    waiters: WaitQueue<bool>,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.waiting_mut().next() {
+            Some(waiter) => {
+                waiter.data = true;
+                waiter.wake();
+            }
+            None => self.permit = true,
+        }
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: IrqSafeMutex::new(State {
+                permit: false,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Waits for a notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            key: None,
+        }
+    }
+
+    /// Wakes the task that waits the longest, or, if none waits, lets the
+    /// next call of `notified` complete right away.
+    ///
+    /// Doesn't allocate, so it can be called by interrupt handlers.
+    pub fn notify_one(&self) {
+        self.state.lock().notify_one();
+    }
+
+    /// Wakes all waiting tasks. Unlike `notify_one`, nothing is stored if
+    /// none waits.
+    pub fn notify_waiters(&self) {
+        self.state.lock().waiters.wake_all();
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+/// The future returned by `Notify::notified`.
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    key: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let mut state = this.notify.state.lock();
+        let notified = match this.key {
+            None if state.permit => {
+                state.permit = false;
+                true
+            }
+            None => {
+                this.key = Some(state.waiters.push(cx.waker(), false));
+                false
+            }
+            Some(key) => state.waiters.poll(key, cx.waker()),
+        };
+        if notified {
+            this.key = None;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let mut state = self.notify.state.lock();
+            // pass on a `notify_one` it didn't get to see
+            if let Some(waiter) = state.waiters.remove(key) {
+                if waiter.woken && waiter.data {
+                    state.notify_one();
+                }
+            }
+        }
+    }
+}
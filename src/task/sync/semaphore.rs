@@ -0,0 +1,161 @@
+// a counting semaphore whose waiters get their permits in FIFO order
+use super::wait_queue::WaitQueue;
+use crate::lock::IrqSafeMutex;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub struct Semaphore {
+    state: IrqSafeMutex<State>,
+}
+
+struct State {
+    permits: usize,
+    /// Each waiter with the number of permits it needs.
+    waiters: WaitQueue<usize>,
+}
+
+impl State {
+    /// Hands out permits to the waiters in order, until the next one needs
+    /// more than are left.
+    fn grant(&mut self) {
+        for waiter in self.waiters.waiting_mut() {
+            if waiter.data > self.permits {
+                break;
+            }
+            self.permits -= waiter.data;
+            waiter.wake();
+        }
+    }
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: IrqSafeMutex::new(State {
+                permits,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    /// Adds `permits` permits, handing them to waiting tasks first.
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock();
+        state.permits += permits;
+        state.grant();
+    }
+
+    /// Waits for a permit.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits until `permits` permits can be taken at once. Tasks that called
+    /// it earlier get theirs first.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            key: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes `permits` permits if they are available and no task is waiting
+    /// already.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if state.waiters.has_waiting() || state.permits < permits {
+            return None;
+        }
+        state.permits -= permits;
+        Some(SemaphorePermit {
+            semaphore: self,
+            permits,
+        })
+    }
+}
+
+/// The future returned by `Semaphore::acquire` and `acquire_many`.
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    /// The key of the parked waiter.
+    key: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let this = self.get_mut();
+        let mut state = this.semaphore.state.lock();
+        let acquired = match this.key {
+            // waiting tasks go first
+            None if !state.waiters.has_waiting() && state.permits >= this.permits => {
+                state.permits -= this.permits;
+                true
+            }
+            None => {
+                this.key = Some(state.waiters.push(cx.waker(), this.permits));
+                false
+            }
+            // woken waiters were granted their permits already
+            Some(key) => state.waiters.poll(key, cx.waker()),
+        };
+        if !acquired {
+            return Poll::Pending;
+        }
+        this.key = None;
+        Poll::Ready(SemaphorePermit {
+            semaphore: this.semaphore,
+            permits: this.permits,
+        })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let mut state = self.semaphore.state.lock();
+            // give back the permits it was granted but never took
+            if let Some(waiter) = state.waiters.remove(key) {
+                if waiter.woken {
+                    state.permits += self.permits;
+                }
+            }
+            // it may have kept the waiters behind it from their permits
+            state.grant();
+        }
+    }
+}
+
+/// Permits taken from a `Semaphore`, given back when dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits from being given back.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
@@ -0,0 +1,79 @@
+// the parked waiters of a synchronization primitive
+use alloc::collections::BTreeMap;
+use core::task::Waker;
+
+pub(in crate::task) struct Waiter<T> {
+    waker: Waker,
+    /// Whether the waiter was woken. It removes itself once it notices.
+    pub woken: bool,
+    /// What the primitive needs to know about the waiter.
+    pub data: T,
+}
+
+impl<T> Waiter<T> {
+    pub fn wake(&mut self) {
+        if !self.woken {
+            self.woken = true;
+            self.waker.wake_by_ref();
+        }
+    }
+}
+
+pub(in crate::task) struct WaitQueue<T = ()> {
+    /// Keys are handed out in increasing order, so the map is in FIFO order.
+    waiters: BTreeMap<u64, Waiter<T>>,
+    next_key: u64,
+}
+
+impl<T> WaitQueue<T> {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: BTreeMap::new(),
+            next_key: 0,
+        }
+    }
+
+    /// Parks a waiter at the end of the queue and returns its key.
+    pub fn push(&mut self, waker: &Waker, data: T) -> u64 {
+        let key = self.next_key;
+        self.next_key += 1;
+        let waiter = Waiter {
+            waker: waker.clone(),
+            woken: false,
+            data,
+        };
+        self.waiters.insert(key, waiter);
+        key
+    }
+
+    /// Removes the waiter if it was woken and returns `true`, otherwise
+    /// updates its waker.
+    pub fn poll(&mut self, key: u64, waker: &Waker) -> bool {
+        let waiter = self.waiters.get_mut(&key).expect("waiter not in queue");
+        if waiter.woken {
+            self.waiters.remove(&key);
+            return true;
+        }
+        if !waiter.waker.will_wake(waker) {
+            waiter.waker = waker.clone();
+        }
+        false
+    }
+
+    pub fn remove(&mut self, key: u64) -> Option<Waiter<T>> {
+        self.waiters.remove(&key)
+    }
+
+    /// The waiters that were not woken yet, first come first.
+    pub fn waiting_mut(&mut self) -> impl Iterator<Item = &mut Waiter<T>> {
+        self.waiters.values_mut().filter(|waiter| !waiter.woken)
+    }
+
+    pub fn has_waiting(&self) -> bool {
+        self.waiters.values().any(|waiter| !waiter.woken)
+    }
+
+    pub fn wake_all(&mut self) {
+        self.waiting_mut().for_each(Waiter::wake);
+    }
+}
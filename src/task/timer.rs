@@ -0,0 +1,195 @@
+// sleep and timeout futures, woken by the timer interrupt
+use crate::time::{self, Duration, Instant, TIMER_FREQUENCY_HZ};
+use alloc::collections::{btree_map::Entry, BTreeMap};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use crate::lock::IrqSafeMutex;
+
+/// A registered timer: the waker of the sleeping task and whether the timer
+/// interrupt woke it already.
+struct TimerEntry {
+    waker: Waker,
+    woken: bool,
+}
+
+/// The registered timers, ordered by the tick they expire at (and an ID to
+/// tell apart timers of the same tick).
+static TIMERS: IrqSafeMutex<BTreeMap<(u64, u64), TimerEntry>> =
+    IrqSafeMutex::new(BTreeMap::new());
+/// The earliest tick a timer that wasn't woken yet expires at, so that the
+/// interrupt handler only has to look at `TIMERS` when necessary.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate, so expired timers are only woken here and
+/// removed by their futures.
+pub(crate) fn expire(now: u64) {
+    if now < NEXT_DEADLINE.load(Ordering::Relaxed) {
+        return;
+    }
+    // the timers are locked by a task; try again on the next tick
+    let mut timers = match TIMERS.try_lock() {
+        Some(timers) => timers,
+        None => return,
+    };
+    for entry in timers.range_mut(..=(now, u64::MAX)).map(|(_, entry)| entry) {
+        if !entry.woken {
+            entry.woken = true;
+            entry.waker.wake_by_ref();
+        }
+    }
+    update_next_deadline(&timers, now);
+}
+
+fn update_next_deadline(timers: &BTreeMap<(u64, u64), TimerEntry>, now: u64) {
+    let next = timers
+        .iter()
+        .find(|((tick, _), entry)| *tick > now || !entry.woken)
+        .map_or(u64::MAX, |((tick, _), _)| *tick);
+    NEXT_DEADLINE.store(next, Ordering::Relaxed);
+}
+
+/// The first tick at which `deadline` will have been reached. It's counted
+/// from the current tick, since `Instant::now` may come from the TSC, which
+/// drifts from the timer interrupt.
+fn deadline_tick(deadline: Instant) -> u64 {
+    let tick_duration = Duration::from_secs(1) / TIMER_FREQUENCY_HZ;
+    let remaining = deadline.duration_since(Instant::now());
+    let remaining_ticks = remaining.as_nanos().div_ceil(tick_duration.as_nanos());
+    time::ticks().saturating_add(remaining_ticks.try_into().unwrap_or(u64::MAX))
+}
+
+/// Waits until `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    // sleep forever on overflow
+    let deadline = Instant::now()
+        .checked_add(duration)
+        .unwrap_or(Instant::from_ticks(u64::MAX));
+    sleep_until(deadline)
+}
+
+/// Waits until `deadline` is reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        key: None,
+    }
+}
+
+/// The future returned by `sleep` and `sleep_until`.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    /// The key of the registered timer.
+    key: Option<(u64, u64)>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    fn register(&mut self, waker: &Waker) {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let tick = deadline_tick(self.deadline);
+        // the lock keeps the interrupt handler out while the timers change
+        let mut timers = TIMERS.lock();
+        // move the timer if it fired before the deadline was reached
+        if let Some(key) = self.key.filter(|key| key.0 != tick) {
+            timers.remove(&key);
+            self.key = None;
+        }
+        let key = *self
+            .key
+            .get_or_insert_with(|| (tick, NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+        match timers.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let entry = entry.get_mut();
+                if !entry.waker.will_wake(waker) {
+                    entry.waker = waker.clone();
+                }
+                entry.woken = false;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(TimerEntry {
+                    waker: waker.clone(),
+                    woken: false,
+                });
+            }
+        }
+        if tick < NEXT_DEADLINE.load(Ordering::Relaxed) {
+            NEXT_DEADLINE.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    fn unregister(&mut self) {
+        if let Some(key) = self.key.take() {
+            // drop the entry after re-enabling interrupts
+            let entry = TIMERS.lock().remove(&key);
+            drop(entry);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let sleep = self.get_mut();
+        if Instant::now() >= sleep.deadline {
+            sleep.unregister();
+            return Poll::Ready(());
+        }
+        sleep.register(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// The error of a `timeout` whose future didn't complete in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs `future` for at most `duration`, resolving to `Err(Elapsed)` if it
+/// didn't complete by then.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// The future returned by `timeout`.
+#[derive(Debug)]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // `future` is structurally pinned: it's never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,450 @@
+// kernel threads: each has its own stack, and they are scheduled round-robin,
+// preempted by the timer interrupt once their time slice is used up
+use crate::memory::stack::{self, KernelStack, StackError};
+use crate::time::{self, Duration, Instant, TIMER_FREQUENCY_HZ};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+mod context;
+
+/// The stack size of a thread, in pages.
+pub const STACK_PAGES: u64 = 16;
+
+/// How many timer ticks a thread runs before the next ready thread gets its
+/// turn.
+pub const TIME_SLICE_TICKS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    /// In the ready queue (or the idle thread while it isn't running).
+    Ready,
+    /// Waiting for the given timer tick.
+    Sleeping(u64),
+    /// Waiting in `JoinHandle::join` for another thread to finish.
+    Joining,
+    /// Done; its stack is freed once it's joined or detached.
+    Finished,
+}
+
+/// A thread control block.
+struct Thread {
+    id: ThreadId,
+    state: State,
+    /// The stack pointer saved by the last switch away from the thread.
+    rsp: u64,
+    /// `None` for the boot thread, which runs on the bootloader's stack.
+    stack: Option<KernelStack>,
+    /// The thread waiting in `join` for this one to finish.
+    joiner: Option<ThreadId>,
+    /// Whether the `JoinHandle` was dropped, so nobody joins the thread.
+    detached: bool,
+}
+
+struct Scheduler {
+    /// The threads are boxed so that their `rsp` stays in place while the
+    /// map changes.
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// The threads ready to run, in round-robin order. A thread is in it at
+    /// most once, and its capacity is kept at the number of threads so that
+    /// the timer interrupt never allocates.
+    ready: VecDeque<ThreadId>,
+    current: ThreadId,
+    /// Runs when no other thread is ready; it's never in `ready`.
+    idle: ThreadId,
+    /// The tick the current thread started running at.
+    slice_start: u64,
+    /// The earliest tick a sleeping thread waits for.
+    next_wake: u64,
+    /// Finished detached threads whose stacks are still to be freed.
+    zombies: Vec<ThreadId>,
+}
+
+impl Scheduler {
+    fn thread(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("unknown thread")
+    }
+
+    fn make_ready(&mut self, id: ThreadId) {
+        self.thread(id).state = State::Ready;
+        debug_assert!(
+            self.ready.len() < self.ready.capacity(),
+            "ready queue would allocate"
+        );
+        self.ready.push_back(id);
+    }
+
+    fn wake_sleepers(&mut self, now: u64) {
+        let mut next_wake = u64::MAX;
+        for thread in self.threads.values_mut() {
+            match thread.state {
+                State::Sleeping(wake_tick) if wake_tick <= now => {
+                    thread.state = State::Ready;
+                    self.ready.push_back(thread.id);
+                }
+                State::Sleeping(wake_tick) => next_wake = next_wake.min(wake_tick),
+                _ => {}
+            }
+        }
+        self.next_wake = next_wake;
+    }
+}
+
+/// Only locked with interrupts disabled, so a thread is never preempted
+/// while holding it.
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// Turns the running code into the first thread and creates the idle
+/// thread. Preemption starts with the next timer interrupt.
+pub fn init() -> Result<(), StackError> {
+    let idle = new_thread(Box::new(idle_loop))?;
+    let boot = Box::new(Thread {
+        id: ThreadId::new(),
+        state: State::Running,
+        rsp: 0,
+        stack: None,
+        joiner: None,
+        detached: true,
+    });
+
+    let scheduler = Scheduler {
+        current: boot.id,
+        idle: idle.id,
+        threads: BTreeMap::from([(boot.id, boot), (idle.id, idle)]),
+        ready: VecDeque::with_capacity(2),
+        slice_start: time::ticks(),
+        next_wake: u64::MAX,
+        zombies: Vec::new(),
+    };
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        assert!(guard.is_none(), "threads already initialized");
+        *guard = Some(scheduler);
+    });
+    Ok(())
+}
+
+/// Creates a thread that runs `entry` once it's switched to.
+fn new_thread(entry: Box<dyn FnOnce() + Send>) -> Result<Box<Thread>, StackError> {
+    let stack = stack::alloc_stack(STACK_PAGES)?;
+    let entry = Box::into_raw(Box::new(entry));
+    let rsp = unsafe { context::init_stack(stack.top(), entry as u64) };
+    Ok(Box::new(Thread {
+        id: ThreadId::new(),
+        state: State::Ready,
+        rsp,
+        stack: Some(stack),
+        joiner: None,
+        detached: false,
+    }))
+}
+
+/// Where new threads start, called with the pointer `new_thread` passed to
+/// `context::init_stack`.
+extern "C" fn thread_entry(entry: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let entry = unsafe { Box::from_raw(entry) };
+    // threads are switched to with interrupts disabled
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+/// Runs when no other thread is ready.
+fn idle_loop() {
+    loop {
+        interrupts::disable();
+        if others_ready() {
+            interrupts::enable();
+            yield_now();
+        } else {
+            // no wakeup can get lost between the check and `hlt`
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// Spawns a thread that runs `f`, returning a handle to join it.
+pub fn spawn<F, T>(f: F) -> Result<JoinHandle<T>, StackError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    reap_zombies();
+
+    let output = Arc::new(Mutex::new(None));
+    let thread_output = output.clone();
+    let thread = new_thread(Box::new(move || {
+        let value = f();
+        *thread_output.lock() = Some(value);
+    }))?;
+
+    let id = thread.id;
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("threads not initialized");
+        scheduler.threads.insert(id, thread);
+        let additional = scheduler
+            .threads
+            .len()
+            .saturating_sub(scheduler.ready.len());
+        scheduler.ready.reserve(additional);
+        scheduler.make_ready(id);
+    });
+    Ok(JoinHandle { id, output })
+}
+
+/// The ID of the running thread.
+pub fn current() -> ThreadId {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_ref()
+            .expect("threads not initialized")
+            .current
+    })
+}
+
+/// Lets the other ready threads run before the current one continues.
+pub fn yield_now() {
+    interrupts::without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        if guard.is_some() {
+            switch_to_next(guard);
+        }
+    });
+}
+
+/// Puts the current thread to sleep for at least `duration`.
+pub fn sleep(duration: Duration) {
+    // sleep forever on overflow
+    let deadline = Instant::now()
+        .checked_add(duration)
+        .unwrap_or(Instant::from_ticks(u64::MAX));
+    // the current tick may be almost over, so the thread can wake a bit
+    // early and sleeps again for the rest
+    while Instant::now() < deadline {
+        let tick_duration = Duration::from_secs(1) / TIMER_FREQUENCY_HZ;
+        let remaining = deadline.duration_since(Instant::now());
+        let ticks = remaining.as_nanos().div_ceil(tick_duration.as_nanos());
+        sleep_until(time::ticks().saturating_add(ticks.try_into().unwrap_or(u64::MAX)));
+    }
+}
+
+/// Puts the current thread to sleep until the timer tick `wake_tick`.
+fn sleep_until(wake_tick: u64) {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("threads not initialized");
+        let current = scheduler.current;
+        scheduler.thread(current).state = State::Sleeping(wake_tick);
+        scheduler.next_wake = scheduler.next_wake.min(wake_tick);
+        switch_to_next(guard);
+    });
+}
+
+/// Whether a thread other than the current one is ready to run.
+pub(crate) fn others_ready() -> bool {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_ref()
+            .map_or(false, |scheduler| !scheduler.ready.is_empty())
+    })
+}
+
+/// Called by the timer interrupt handler once the interrupt was
+/// acknowledged: wakes the sleeping threads that are due and preempts the
+/// current thread when its time slice is used up.
+///
+/// Must not allocate, so it only moves IDs into `ready`, which has room for
+/// all threads.
+pub(crate) fn tick(now: u64) {
+    let mut guard = match SCHEDULER.try_lock() {
+        Some(guard) => guard,
+        None => return,
+    };
+    let scheduler = match guard.as_mut() {
+        Some(scheduler) => scheduler,
+        None => return, // threads are not initialized yet
+    };
+
+    if now >= scheduler.next_wake {
+        scheduler.wake_sleepers(now);
+    }
+    let slice_used = now.saturating_sub(scheduler.slice_start) >= TIME_SLICE_TICKS;
+    if !scheduler.ready.is_empty() && (slice_used || scheduler.current == scheduler.idle) {
+        switch_to_next(guard);
+    }
+}
+
+/// Switches to the next ready thread, or to the idle thread if there is
+/// none and the current thread can't go on. The current thread is queued
+/// again if it is still running, i.e. it yields or is preempted.
+///
+/// Interrupts must be disabled. The thread switched to enables them again.
+fn switch_to_next(mut guard: MutexGuard<Option<Scheduler>>) {
+    // the next thread would run with the locks of this one held
+    crate::lock::assert_none_held();
+    let scheduler = guard.as_mut().expect("threads not initialized");
+    let current = scheduler.current;
+    let still_running = scheduler.thread(current).state == State::Running;
+    let next = match scheduler.ready.pop_front() {
+        Some(next) => next,
+        None if still_running => return,
+        None => scheduler.idle,
+    };
+
+    if still_running {
+        if current == scheduler.idle {
+            scheduler.thread(current).state = State::Ready;
+        } else {
+            scheduler.make_ready(current);
+        }
+    }
+    scheduler.thread(next).state = State::Running;
+    scheduler.current = next;
+    scheduler.slice_start = time::ticks();
+
+    let current_rsp: *mut u64 = &mut scheduler.thread(current).rsp;
+    let next_rsp = scheduler.thread(next).rsp;
+    // nothing else runs before interrupts are enabled again, so the boxed
+    // threads stay in place without the lock
+    drop(guard);
+    unsafe { context::switch(current_rsp, next_rsp) };
+}
+
+/// Ends the current thread.
+fn exit() -> ! {
+    interrupts::disable();
+    let mut guard = SCHEDULER.lock();
+    let scheduler = guard.as_mut().expect("threads not initialized");
+    let current = scheduler.current;
+    let thread = scheduler.thread(current);
+    thread.state = State::Finished;
+    let joiner = thread.joiner.take();
+    if thread.detached {
+        scheduler.zombies.push(current);
+    }
+    if let Some(joiner) = joiner {
+        scheduler.make_ready(joiner);
+    }
+    switch_to_next(guard);
+    unreachable!("finished thread was resumed");
+}
+
+/// Removes the thread `id` if it finished, and frees its stack.
+fn reap(id: ThreadId) {
+    let thread = interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut()?;
+        match scheduler.threads.get(&id) {
+            Some(thread) if thread.state == State::Finished => scheduler.threads.remove(&id),
+            _ => None,
+        }
+    });
+    // a finished thread never runs again, so its stack is unused
+    if let Some(stack) = thread.and_then(|thread| thread.stack) {
+        unsafe { stack::free_stack(stack) };
+    }
+}
+
+/// Frees the stacks of the finished detached threads.
+fn reap_zombies() {
+    let zombies = interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_mut()
+            .map(|scheduler| core::mem::take(&mut scheduler.zombies))
+    });
+    for id in zombies.into_iter().flatten() {
+        reap(id);
+    }
+}
+
+/// Owned permission to join a thread.
+///
+/// Dropping the handle detaches the thread, it keeps running.
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    output: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let guard = SCHEDULER.lock();
+            let scheduler = guard.as_ref().expect("threads not initialized");
+            scheduler
+                .threads
+                .get(&self.id)
+                .map_or(true, |thread| thread.state == State::Finished)
+        })
+    }
+
+    /// Waits for the thread to finish and returns what it returned.
+    pub fn join(self) -> T {
+        interrupts::without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut().expect("threads not initialized");
+            let current = scheduler.current;
+            assert_ne!(self.id, current, "thread joined itself");
+            let thread = scheduler.thread(self.id);
+            if thread.state != State::Finished {
+                thread.joiner = Some(current);
+                scheduler.thread(current).state = State::Joining;
+                switch_to_next(guard);
+            }
+        });
+        reap(self.id);
+        self.output
+            .lock()
+            .take()
+            .expect("joined thread has no output")
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        // nothing to do if the thread was joined and removed already
+        let finished = interrupts::without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut().expect("threads not initialized");
+            match scheduler.threads.get_mut(&self.id) {
+                Some(thread) if thread.state == State::Finished => true,
+                Some(thread) => {
+                    thread.detached = true;
+                    false
+                }
+                None => false,
+            }
+        });
+        if finished {
+            reap(self.id);
+        }
+    }
+}
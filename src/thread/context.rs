@@ -0,0 +1,65 @@
+// switching between the stacks of kernel threads
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+
+// Saves the callee-saved registers on the current stack, stores the stack
+// pointer at `[rdi]` and continues on the stack `rsi` was saved from. The
+// caller-saved registers are saved by the compiler around the call.
+global_asm!(
+    ".pushsection .text",
+    ".global switch_context",
+    "switch_context:",
+    "    push rbp",
+    "    push rbx",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rdi], rsp",
+    "    mov rsp, rsi",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+    // the first `switch_context` to a new thread returns here, with the
+    // argument of `entry` in r12 and a 16 byte aligned stack
+    "thread_start:",
+    "    mov rdi, r12",
+    "    call {entry}",
+    "    ud2",
+    ".popsection",
+    entry = sym super::thread_entry,
+);
+
+extern "C" {
+    fn switch_context(current_rsp: *mut u64, next_rsp: u64);
+    fn thread_start();
+}
+
+/// Stores the stack pointer of the current thread in `current_rsp` and
+/// resumes the thread whose stack pointer is `next_rsp`. Returns once a
+/// switch back to the current thread happens.
+///
+/// This function is unsafe because `next_rsp` must come from `switch` or
+/// `init_stack`, and interrupts must be disabled.
+pub unsafe fn switch(current_rsp: *mut u64, next_rsp: u64) {
+    switch_context(current_rsp, next_rsp);
+}
+
+/// Prepares a new stack so that switching to it calls `thread_entry(arg)`,
+/// and returns the stack pointer to switch to.
+///
+/// This function is unsafe because `stack_top` must be the 16 byte aligned
+/// top of an unused, mapped stack.
+pub unsafe fn init_stack(stack_top: VirtAddr, arg: u64) -> u64 {
+    // what `switch_context` pops: r15, r14, r13, r12, rbx, rbp and the
+    // return address
+    let start = thread_start as unsafe extern "C" fn() as u64;
+    let frame: [u64; 7] = [0, 0, 0, arg, 0, 0, start];
+    let rsp = stack_top - core::mem::size_of_val(&frame) as u64;
+    rsp.as_mut_ptr::<[u64; 7]>().write(frame);
+    rsp.as_u64()
+}
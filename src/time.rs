@@ -0,0 +1,186 @@
+// the monotonic kernel clock, driven by the timer interrupt
+use conquer_once::spin::OnceCell;
+use core::{
+    ops::{Add, AddAssign, Sub, SubAssign},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub use core::time::Duration;
+
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+pub use rtc::DateTime;
+
+/// How often the timer interrupt fires, whether it comes from the PIT or
+/// the local APIC.
+pub const TIMER_FREQUENCY_HZ: u32 = 1000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_TICK: u64 = NANOS_PER_SECOND / TIMER_FREQUENCY_HZ as u64;
+
+/// The number of timer interrupts since `init`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The RTC time in Unix seconds at the given instant, read by
+/// `init_wall_clock`.
+static BOOT_WALL_CLOCK: OnceCell<(u64, Instant)> = OnceCell::uninit();
+
+/// Programs the PIT to `TIMER_FREQUENCY_HZ`.
+pub fn init() {
+    pit::set_frequency(TIMER_FREQUENCY_HZ);
+}
+
+/// Reads the RTC that `wall_clock` counts from. Should be called after
+/// `acpi::init`, which finds the century register.
+pub fn init_wall_clock() {
+    BOOT_WALL_CLOCK.init_once(|| (rtc::read().to_unix_seconds(), Instant::now()));
+}
+
+/// Counts a timer interrupt and returns the new number of ticks.
+pub(crate) fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// The number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The current time, with nanosecond resolution once `tsc::init` succeeded.
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// The current date and time, from the RTC reading at boot and the
+/// monotonic clock since then.
+pub fn wall_clock() -> DateTime {
+    match BOOT_WALL_CLOCK.try_get() {
+        Ok((seconds, instant)) => DateTime::from_unix_seconds(seconds + instant.elapsed().as_secs()),
+        Err(_) => rtc::read(),
+    }
+}
+
+/// The time since boot.
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant::BOOT)
+}
+
+/// A point in time measured by the monotonic kernel clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    /// Nanoseconds since boot.
+    nanos: u64,
+}
+
+impl Instant {
+    /// The instant the clock started at.
+    pub const BOOT: Instant = Instant { nanos: 0 };
+
+    pub fn now() -> Instant {
+        match tsc::nanos_since_boot() {
+            Some(nanos) => Instant { nanos },
+            None => Instant::from_ticks(ticks()),
+        }
+    }
+
+    /// The instant of the given timer tick.
+    pub fn from_ticks(ticks: u64) -> Instant {
+        Instant {
+            nanos: ticks.saturating_mul(NANOS_PER_TICK),
+        }
+    }
+
+    /// Nanoseconds since boot.
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// The last timer tick at or before this instant.
+    pub fn ticks(&self) -> u64 {
+        self.nanos / NANOS_PER_TICK
+    }
+
+    /// The time elapsed from `earlier` to `self`, or zero if `earlier` is
+    /// later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant {
+            nanos: self.nanos.checked_sub(nanos)?,
+        })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+#[test_case]
+fn test_instant_arithmetic() {
+    let start = Instant::from_ticks(5);
+    let later = start + Duration::from_millis(20);
+    assert_eq!(later - start, Duration::from_millis(20));
+    assert_eq!(start - later, Duration::ZERO);
+    assert_eq!(later.ticks(), 5 + 20 * TIMER_FREQUENCY_HZ as u64 / 1000);
+    assert_eq!(later - Duration::from_millis(20), start);
+    assert_eq!(start.checked_sub(Duration::from_secs(1)), None);
+}
+
+#[test_case]
+fn test_clock_advances() {
+    let start = Instant::now();
+    while ticks() < start.ticks() + 3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(start.elapsed() >= Duration::from_nanos(2 * NANOS_PER_TICK));
+}
@@ -0,0 +1,50 @@
+// the 8253/8254 programmable interval timer
+use x86_64::instructions::port::Port;
+
+/// The frequency the PIT's counters run at.
+pub const PIT_FREQUENCY_HZ: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// Gates channel 2 (bit 0), connects it to the speaker (bit 1) and reads its
+/// output (bit 5).
+const CHANNEL_2_GATE_PORT: u16 = 0x61;
+
+/// Programs channel 0, which raises IRQ 0, to fire `frequency_hz` times a
+/// second.
+pub fn set_frequency(frequency_hz: u32) {
+    let divisor = (PIT_FREQUENCY_HZ / frequency_hz).clamp(1, u16::MAX as u32) as u16;
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0_PORT);
+    unsafe {
+        // channel 0, lobyte/hibyte, mode 2 (rate generator)
+        command.write(0b0011_0100);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Starts counting down `count` PIT ticks on channel 2 without sounding the
+/// speaker; `countdown_finished` tells when it's done.
+///
+/// Used to calibrate other timers, so channel 2 must not be used otherwise.
+pub fn start_countdown(count: u16) {
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE_PORT);
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut channel_2: Port<u8> = Port::new(CHANNEL_2_PORT);
+    unsafe {
+        let value = gate.read();
+        gate.write((value & !0b10) | 0b01);
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        command.write(0b1011_0000);
+        channel_2.write(count as u8);
+        channel_2.write((count >> 8) as u8);
+    }
+}
+
+/// Whether the countdown started by `start_countdown` reached zero.
+pub fn countdown_finished() -> bool {
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE_PORT);
+    unsafe { gate.read() & 0x20 != 0 }
+}
@@ -0,0 +1,332 @@
+// the CMOS real-time clock
+use crate::acpi;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+const CMOS_INDEX_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+
+// CMOS registers
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+const RTC_STATUS_C: u8 = 0x0c;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0f;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Set in the hours register for PM times in 12 hour mode.
+const HOURS_PM: u8 = 1 << 7;
+
+/// The ISA IRQ of the RTC.
+pub const RTC_IRQ: u8 = 8;
+
+/// The number of periodic interrupts since `enable_periodic_interrupt`.
+static PERIODIC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// A date and time in UTC (which is what the RTC is assumed to hold).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn to_unix_seconds(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        (days * 86400) as u64
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    pub fn from_unix_seconds(seconds: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let seconds_of_day = seconds % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // count years from March, so that the leap day is the last day of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The RTC registers as read, before decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    /// 0 if there is no century register.
+    century: u8,
+}
+
+impl RawTime {
+    /// Converts the registers according to the format given by status
+    /// register B.
+    fn decode(self, status_b: u8) -> DateTime {
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let decode = |value: u8| {
+            if binary {
+                value
+            } else {
+                (value >> 4) * 10 + (value & 0x0f)
+            }
+        };
+
+        let pm = self.hour & HOURS_PM != 0;
+        let mut hour = decode(self.hour & !HOURS_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12 AM is midnight, 12 PM is noon
+            hour = match (hour, pm) {
+                (12, false) => 0,
+                (12, true) => 12,
+                (hour, true) => hour + 12,
+                (hour, false) => hour,
+            };
+        }
+
+        // assume the 21st century without a century register
+        let century = match self.century {
+            0 => 20,
+            century => decode(century) as u16,
+        };
+        DateTime {
+            year: century * 100 + decode(self.year) as u16,
+            month: decode(self.month),
+            day: decode(self.day),
+            hour,
+            minute: decode(self.minute),
+            second: decode(self.second),
+        }
+    }
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    Port::new(CMOS_INDEX_PORT).write(register);
+    Port::new(CMOS_DATA_PORT).read()
+}
+
+unsafe fn write_register(register: u8, value: u8) {
+    Port::new(CMOS_INDEX_PORT).write(register);
+    Port::new(CMOS_DATA_PORT).write(value);
+}
+
+/// Reads the time registers once no update is in progress.
+unsafe fn read_raw(century_register: Option<u8>) -> RawTime {
+    while read_register(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    RawTime {
+        second: read_register(RTC_SECONDS),
+        minute: read_register(RTC_MINUTES),
+        hour: read_register(RTC_HOURS),
+        day: read_register(RTC_DAY),
+        month: read_register(RTC_MONTH),
+        year: read_register(RTC_YEAR),
+        century: century_register.map_or(0, |register| read_register(register)),
+    }
+}
+
+/// Reads the current date and time from the RTC.
+pub fn read() -> DateTime {
+    // the FADT tells whether there is a century register
+    let century_register = acpi::tables()
+        .and_then(|tables| tables.fadt.as_ref())
+        .map(|fadt| fadt.century_register)
+        .filter(|&register| register != 0);
+
+    interrupts::without_interrupts(|| unsafe {
+        // an update can still start while reading, so read until two reads
+        // agree
+        let mut raw = read_raw(century_register);
+        loop {
+            let next = read_raw(century_register);
+            if next == raw {
+                break;
+            }
+            raw = next;
+        }
+        raw.decode(read_register(RTC_STATUS_B))
+    })
+}
+
+/// Makes the RTC raise IRQ 8 at `32768 >> (rate - 1)` Hz, `rate` being
+/// between 3 (8192 Hz) and 15 (2 Hz).
+///
+/// The IRQ is routed through the I/O APIC if it is enabled, otherwise it is
+/// unmasked in the PICs.
+pub fn enable_periodic_interrupt(rate: u8) {
+    use crate::interrupts::{apic, InterruptIndex, PICS};
+
+    assert!((3..=15).contains(&rate), "invalid RTC rate {}", rate);
+    interrupts::without_interrupts(|| unsafe {
+        let status_a = read_register(RTC_STATUS_A);
+        write_register(RTC_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        let status_b = read_register(RTC_STATUS_B);
+        write_register(RTC_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // discard an interrupt that may be pending already
+        read_register(RTC_STATUS_C);
+
+        if apic::is_enabled() {
+            apic::route_isa_irq(RTC_IRQ, InterruptIndex::Rtc as u8);
+        } else {
+            // unmask IRQ 8 on the secondary PIC and the cascade (IRQ 2)
+            let mut pics = PICS.lock();
+            let [mask1, mask2] = pics.read_masks();
+            pics.write_masks(mask1 & !(1 << 2), mask2 & !(1 << (RTC_IRQ - 8)));
+        }
+    });
+}
+
+/// Stops the periodic interrupt of the RTC.
+pub fn disable_periodic_interrupt() {
+    interrupts::without_interrupts(|| unsafe {
+        let status_b = read_register(RTC_STATUS_B);
+        write_register(RTC_STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+    });
+}
+
+/// Called by the RTC interrupt handler
+pub(crate) fn handle_interrupt() {
+    // the RTC raises no further interrupts until status register C is read
+    unsafe { read_register(RTC_STATUS_C) };
+    PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of periodic interrupts the RTC raised.
+pub fn periodic_interrupts() -> u64 {
+    PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    // 2024-02-29 11:59:30 PM in BCD and 12 hour mode
+    let raw = RawTime {
+        second: 0x30,
+        minute: 0x59,
+        hour: HOURS_PM | 0x11,
+        day: 0x29,
+        month: 0x02,
+        year: 0x24,
+        century: 0x20,
+    };
+    let expected = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 59,
+        second: 30,
+    };
+    assert_eq!(raw.decode(0), expected);
+
+    let midnight = RawTime { hour: 0x12, ..raw };
+    assert_eq!(midnight.decode(0).hour, 0);
+}
+
+#[test_case]
+fn test_decode_binary_24_hour() {
+    let raw = RawTime {
+        second: 5,
+        minute: 4,
+        hour: 13,
+        day: 31,
+        month: 12,
+        year: 99,
+        century: 0,
+    };
+    let date_time = raw.decode(STATUS_B_BINARY | STATUS_B_24_HOUR);
+    assert_eq!((date_time.year, date_time.month, date_time.day), (2099, 12, 31));
+    assert_eq!((date_time.hour, date_time.minute, date_time.second), (13, 4, 5));
+}
+
+#[test_case]
+fn test_unix_seconds() {
+    use alloc::string::ToString;
+
+    let date_time = DateTime::from_unix_seconds(0);
+    assert_eq!((date_time.year, date_time.month, date_time.day), (1970, 1, 1));
+
+    // 2024-02-29 23:59:30
+    let date_time = DateTime::from_unix_seconds(1_709_251_170);
+    assert_eq!(date_time.to_string(), "2024-02-29 23:59:30");
+    assert_eq!(date_time.to_unix_seconds(), 1_709_251_170);
+}
+
+#[test_case]
+fn test_read_rtc() {
+    let date_time = read();
+    assert!(date_time.year >= 2020);
+    assert!((1..=12).contains(&date_time.month));
+    assert!((1..=31).contains(&date_time.day));
+    assert!(date_time.hour < 24 && date_time.minute < 60 && date_time.second < 60);
+}
+
+#[test_case]
+fn test_periodic_interrupt() {
+    let start = periodic_interrupts();
+    // 1024 Hz
+    enable_periodic_interrupt(6);
+    while periodic_interrupts() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+    disable_periodic_interrupt();
+}
@@ -0,0 +1,148 @@
+// the time stamp counter, used for nanosecond timestamps once calibrated
+use super::{pit, Instant, NANOS_PER_SECOND};
+use crate::{acpi, memory};
+use conquer_once::spin::OnceCell;
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc},
+    ptr,
+};
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{mapper::MapToError, Size4KiB},
+};
+
+/// How long a single calibration run takes.
+const CALIBRATION_MILLIS: u64 = 10;
+/// Calibration is repeated and the shortest run taken, since interruptions
+/// (e.g. by SMIs) only make runs longer.
+const CALIBRATION_RUNS: usize = 3;
+
+// HPET registers
+const HPET_CAPABILITIES: usize = 0x00;
+const HPET_CONFIGURATION: usize = 0x10;
+const HPET_MAIN_COUNTER: usize = 0xf0;
+const HPET_COUNTER_64_BIT: u64 = 1 << 13;
+const HPET_ENABLE: u64 = 1 << 0;
+const FEMTOS_PER_SECOND: u128 = 1_000_000_000_000_000;
+
+#[derive(Debug)]
+pub enum TscError {
+    /// The TSC doesn't run at a constant rate in all power states.
+    NotInvariant,
+    /// Mapping the HPET registers failed.
+    Map(MapToError<Size4KiB>),
+}
+
+/// What's needed to convert TSC values to `Instant`s.
+#[derive(Debug)]
+struct Calibration {
+    frequency_hz: u64,
+    /// The TSC value at `base_nanos`.
+    base_tsc: u64,
+    base_nanos: u64,
+    /// Nanoseconds per TSC tick as a 32.32 fixed point number.
+    nanos_per_tsc_tick: u64,
+}
+
+static CALIBRATION: OnceCell<Calibration> = OnceCell::uninit();
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Whether the TSC runs at a constant rate, i.e. CPUID.80000007h:EDX[8].
+pub fn is_invariant() -> bool {
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    max_extended_leaf >= 0x8000_0007 && unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8) != 0
+}
+
+/// Calibrates the TSC against the HPET (if `acpi::init` found one) or the PIT
+/// and makes `Instant::now` use it. Returns the frequency of the TSC.
+///
+/// Must be called after `memory::init_kernel_memory`.
+pub fn init() -> Result<u64, TscError> {
+    if !is_invariant() {
+        return Err(TscError::NotInvariant);
+    }
+
+    let hpet = acpi::tables().and_then(|tables| tables.hpet.as_ref());
+    let frequency_hz = match hpet {
+        Some(hpet) => {
+            let registers = unsafe { memory::map_mmio(hpet.base_address, 0x400) }
+                .map_err(TscError::Map)?;
+            let registers: *mut u64 = registers.as_mut_ptr();
+            min_run(|| unsafe { calibrate_with_hpet(registers) })
+        }
+        None => min_run(calibrate_with_pit),
+    };
+
+    let calibration = interrupts::without_interrupts(|| Calibration {
+        frequency_hz,
+        base_tsc: read(),
+        base_nanos: Instant::from_ticks(super::ticks()).nanos,
+        nanos_per_tsc_tick: ((NANOS_PER_SECOND as u128) << 32)
+            .checked_div(frequency_hz as u128)
+            .unwrap_or(0) as u64,
+    });
+    CALIBRATION.init_once(|| calibration);
+    Ok(frequency_hz)
+}
+
+/// The calibrated frequency of the TSC, `None` until `init` succeeded.
+pub fn frequency() -> Option<u64> {
+    CALIBRATION.try_get().ok().map(|calibration| calibration.frequency_hz)
+}
+
+/// Nanoseconds since boot according to the TSC, `None` until `init`
+/// succeeded.
+pub(super) fn nanos_since_boot() -> Option<u64> {
+    let calibration = CALIBRATION.try_get().ok()?;
+    let elapsed = read().saturating_sub(calibration.base_tsc) as u128;
+    let nanos = (elapsed * calibration.nanos_per_tsc_tick as u128) >> 32;
+    Some(calibration.base_nanos + nanos as u64)
+}
+
+/// Runs `calibrate` `CALIBRATION_RUNS` times with interrupts disabled and
+/// turns the shortest run into a frequency.
+fn min_run(mut calibrate: impl FnMut() -> u64) -> u64 {
+    let tsc_ticks = interrupts::without_interrupts(|| {
+        (0..CALIBRATION_RUNS).map(|_| calibrate()).min().unwrap()
+    });
+    tsc_ticks * (1000 / CALIBRATION_MILLIS)
+}
+
+/// Counts the TSC ticks while channel 2 of the PIT counts down.
+fn calibrate_with_pit() -> u64 {
+    let pit_count = pit::PIT_FREQUENCY_HZ as u64 * CALIBRATION_MILLIS / 1000;
+    pit::start_countdown(pit_count as u16);
+    let start = read();
+    while !pit::countdown_finished() {}
+    read() - start
+}
+
+/// Counts the TSC ticks while the main counter of the HPET advances.
+///
+/// This function is unsafe because `registers` must point to the mapped
+/// HPET registers.
+unsafe fn calibrate_with_hpet(registers: *mut u64) -> u64 {
+    let register = |offset: usize| registers.add(offset / 8);
+
+    // the upper half of the capabilities is the counter period in femtoseconds
+    let capabilities = ptr::read_volatile(register(HPET_CAPABILITIES));
+    let period_fs = (capabilities >> 32) as u128;
+    let counter_mask = match capabilities & HPET_COUNTER_64_BIT {
+        0 => u32::MAX as u64,
+        _ => u64::MAX,
+    };
+    let configuration = ptr::read_volatile(register(HPET_CONFIGURATION));
+    ptr::write_volatile(register(HPET_CONFIGURATION), configuration | HPET_ENABLE);
+
+    let hpet_ticks = (FEMTOS_PER_SECOND * CALIBRATION_MILLIS as u128 / 1000 / period_fs) as u64;
+    let hpet_start = ptr::read_volatile(register(HPET_MAIN_COUNTER));
+    let start = read();
+    while ptr::read_volatile(register(HPET_MAIN_COUNTER)).wrapping_sub(hpet_start) & counter_mask
+        < hpet_ticks
+    {}
+    read() - start
+}
@@ -3,7 +3,7 @@ use volatile::Volatile;
 use core::fmt;
 
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::lock::TicketMutex;
 
 use crate::interrupts::{TICKER, TICKER_BOOLEAN};
 
@@ -219,8 +219,9 @@ impl fmt::Write for Writer {
 // global interface that can be used without having to carry a Writer instance to each module!
 // Lazy static lets us initialize the WRITER when called instead of on compile time, which results in errors
 // Mutex allows us to safely have interior mutability to our WRITER, allowing us to change it
+// (and keeps interrupts disabled while it's locked, so printing can't deadlock)
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: TicketMutex<Writer> = TicketMutex::new(Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
@@ -253,32 +254,18 @@ macro_rules! write_cursor {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    // prevents deadlocks
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    });
+    WRITER.lock().write_fmt(args).unwrap();
 }
 
 #[doc(hidden)]
 pub fn _delete() {
-    use x86_64::instructions::interrupts;
-
-    // prevents deadlocks
-    interrupts::without_interrupts(|| {
-        WRITER.lock().delete_byte();
-    });
+    WRITER.lock().delete_byte();
 }
 
 #[doc(hidden)]
 pub fn _write_cursor() {
-    use x86_64::instructions::interrupts;
-
-    // prevents deadlocks
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_cursor();
-    });
+    WRITER.lock().write_cursor();
 }
 
 // prints some random text to the screen
@@ -327,15 +314,12 @@ fn test_println_many() {
 #[test_case]
 fn test_println_output() {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     let s = "Some test string that fits on a single line";
-    interrupts::without_interrupts(|| {
-        let mut writer = WRITER.lock();
-        writeln!(writer, "\n{}", s).expect("writeln failed");
-        for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
-            assert_eq!(char::from(screen_char.ascii_character), c);
-        }
-    });
+    let mut writer = WRITER.lock();
+    writeln!(writer, "\n{}", s).expect("writeln failed");
+    for (i, c) in s.chars().enumerate() {
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        assert_eq!(char::from(screen_char.ascii_character), c);
+    }
 }
@@ -0,0 +1,8 @@
+#!/bin/sh
+# runs the heap_allocation test suite once for every heap allocator
+set -e
+
+for allocator in bump linked-list fixed-block buddy; do
+    echo "heap_allocation with alloc-$allocator"
+    cargo +nightly test --test heap_allocation --features "alloc-$allocator"
+done
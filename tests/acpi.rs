@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::{acpi, memory};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::BitmapFrameAllocator;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    acpi::init().expect("ACPI initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_lists_boot_cpu_and_io_apic() {
+    let madt = acpi::tables().unwrap().madt.as_ref().expect("no MADT");
+    assert!(madt.processors.iter().any(|processor| processor.enabled));
+    assert!(!madt.io_apics.is_empty());
+    // QEMU routes the PIT IRQ to GSI 2
+    assert_eq!(madt.interrupt_override(0).map(|o| o.gsi), Some(2));
+}
+
+#[test_case]
+fn fadt_has_pm1a_control_block() {
+    let fadt = acpi::tables().unwrap().fadt.as_ref().expect("no FADT");
+    assert_ne!(fadt.pm1a_control_block, 0);
+    assert_ne!(fadt.dsdt.as_u64(), 0);
+}
+
+#[test_case]
+fn hpet_is_memory_mapped() {
+    let hpet = acpi::tables().unwrap().hpet.as_ref().expect("no HPET");
+    assert_ne!(hpet.base_address.as_u64(), 0);
+    assert!(hpet.comparator_count >= 3);
+}
@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use morb_os::task::{
+    channel::{broadcast, mpsc, oneshot, Closed},
+    executor::Executor,
+    yield_now,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn bounded_try_send_reports_full_and_closed() {
+    let (sender, mut receiver) = mpsc::channel(2);
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(mpsc::TrySendError::Full(3)));
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(sender.try_send(3), Ok(()));
+    drop(receiver);
+    assert!(sender.is_closed());
+    assert_eq!(sender.try_send(4), Err(mpsc::TrySendError::Closed(4)));
+}
+
+#[test_case]
+fn bounded_send_waits_for_room() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let received = executor.block_on(async move {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let producers: Vec<_> = (0..3)
+            .map(|i| {
+                let sender = sender.clone();
+                spawner.spawn(async move { sender.send(i).await.unwrap() })
+            })
+            .collect();
+        drop(sender);
+        // let all of them queue up behind the first
+        yield_now().await;
+        yield_now().await;
+        assert!(!producers[2].is_finished());
+        let mut received = Vec::new();
+        while let Some(value) = receiver.recv().await {
+            received.push(value);
+        }
+        for producer in producers {
+            producer.await.unwrap();
+        }
+        received
+    });
+    // waiting senders send in the order they started waiting
+    assert_eq!(received, [0, 1, 2]);
+}
+
+#[test_case]
+fn unbounded_send_never_waits() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    for i in 0..100 {
+        sender.try_send(i).unwrap();
+    }
+    drop(sender);
+    for i in 0..100 {
+        assert_eq!(receiver.try_recv(), Ok(i));
+    }
+    assert_eq!(receiver.try_recv(), Err(mpsc::TryRecvError::Closed));
+}
+
+#[test_case]
+fn dropped_receiver_fails_waiting_senders() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.block_on(async move {
+        let (sender, receiver) = mpsc::channel(1);
+        sender.send(0).await.unwrap();
+        let waiting = spawner.spawn(async move { sender.send(1).await });
+        yield_now().await;
+        yield_now().await;
+        drop(receiver);
+        assert_eq!(waiting.await.unwrap(), Err(mpsc::SendError(1)));
+    });
+}
+
+#[test_case]
+fn mpsc_stream_and_sink() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let sum = executor.block_on(async move {
+        let (mut sender, receiver) = mpsc::channel(2);
+        let producer = spawner.spawn(async move {
+            for i in 1..=10 {
+                SinkExt::send(&mut sender, i).await.unwrap();
+            }
+        });
+        let sum = receiver
+            .fold(0, |sum, value| async move { sum + value })
+            .await;
+        producer.await.unwrap();
+        sum
+    });
+    assert_eq!(sum, 55);
+}
+
+#[test_case]
+fn oneshot_delivers_or_cancels() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.block_on(async move {
+        let (sender, receiver) = oneshot::channel();
+        spawner.spawn(async move { sender.send(42).unwrap() });
+        assert_eq!(receiver.await, Ok(42));
+
+        let (sender, receiver) = oneshot::channel::<u32>();
+        drop(sender);
+        assert_eq!(receiver.await, Err(oneshot::Canceled));
+
+        let (sender, receiver) = oneshot::channel();
+        drop(receiver);
+        assert_eq!(sender.send(1), Err(1));
+    });
+}
+
+#[test_case]
+fn broadcast_reaches_every_receiver() {
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let (mut sender, mut first) = broadcast::channel(4);
+        let mut second = sender.subscribe();
+        assert_eq!(sender.send(1), Ok(2));
+        sender.send(2).unwrap();
+        assert_eq!(first.recv().await, Ok(1));
+        assert_eq!(first.recv().await, Ok(2));
+        assert_eq!(second.recv().await, Ok(1));
+        // a late subscriber only sees what is sent from now on
+        let mut late = sender.subscribe();
+        sender.feed(3).await.unwrap();
+        drop(sender);
+        assert_eq!(late.recv().await, Ok(3));
+        assert_eq!(late.recv().await, Err(broadcast::RecvError::Closed));
+        let rest: Vec<_> = second.collect().await;
+        assert_eq!(rest, [Ok(2), Ok(3)]);
+    });
+}
+
+#[test_case]
+fn broadcast_receiver_lags_behind() {
+    let (sender, mut receiver) = broadcast::channel(2);
+    for i in 0..5 {
+        sender.send(i).unwrap();
+    }
+    assert_eq!(receiver.try_recv(), Err(broadcast::TryRecvError::Lagged(3)));
+    assert_eq!(receiver.try_recv(), Ok(3));
+    assert_eq!(receiver.try_recv(), Ok(4));
+    assert_eq!(receiver.try_recv(), Err(broadcast::TryRecvError::Empty));
+    drop(receiver);
+    assert_eq!(sender.send(5), Err(broadcast::SendError(5)));
+}
+
+#[test_case]
+fn sink_fails_once_closed() {
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let (mut sender, receiver) = mpsc::channel::<u32>(1);
+        drop(receiver);
+        assert_eq!(SinkExt::send(&mut sender, 1).await, Err(Closed));
+    });
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::memory::{self, vma::{self, ReserveError, VmAreaKind}};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::BitmapFrameAllocator;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+const AREA_START: u64 = 0x_5555_0000_0000;
+const AREA_SIZE: u64 = 16 * 4096;
+
+#[test_case]
+fn anonymous_memory_is_mapped_on_access() {
+    let start = VirtAddr::new(AREA_START);
+    vma::reserve(start, AREA_SIZE, VmAreaKind::Anonymous, PageTableFlags::WRITABLE).unwrap();
+    assert_eq!(memory::translate(start), None);
+
+    let ptr: *mut u64 = start.as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+
+        // the last page of the area is mapped independently
+        let last = ptr.add((AREA_SIZE / 8) as usize - 1);
+        last.write_volatile(7);
+        assert_eq!(last.read_volatile(), 7);
+    }
+    assert!(memory::translate(start).is_some());
+
+    unsafe { vma::release(start).unwrap() };
+    assert_eq!(memory::translate(start), None);
+}
+
+#[test_case]
+fn released_memory_is_zeroed_again() {
+    let start = VirtAddr::new(AREA_START);
+    let ptr: *mut u64 = start.as_mut_ptr();
+    for _ in 0..2 {
+        vma::reserve(start, AREA_SIZE, VmAreaKind::Anonymous, PageTableFlags::WRITABLE).unwrap();
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(1);
+            vma::release(start).unwrap();
+        }
+    }
+}
+
+#[test_case]
+fn overlapping_areas_are_rejected() {
+    let start = VirtAddr::new(AREA_START);
+    vma::reserve(start, AREA_SIZE, VmAreaKind::Guard, PageTableFlags::empty()).unwrap();
+
+    let overlap = vma::reserve(start + 4096u64, AREA_SIZE, VmAreaKind::Anonymous, PageTableFlags::WRITABLE);
+    assert!(matches!(overlap, Err(ReserveError::Overlap(_))));
+    let unaligned = vma::reserve(start + AREA_SIZE + 8u64, 4096, VmAreaKind::Anonymous, PageTableFlags::WRITABLE);
+    assert!(matches!(unaligned, Err(ReserveError::NotPageAligned)));
+    assert_eq!(vma::find(start + 4096u64).map(|area| area.kind), Some(VmAreaKind::Guard));
+
+    unsafe { vma::release(start).unwrap() };
+    assert_eq!(vma::find(start), None);
+}
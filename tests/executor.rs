@@ -0,0 +1,214 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    future::Future,
+    panic::PanicInfo,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use morb_os::{
+    task::{executor::Executor, timer, yield_now, JoinError, Priority},
+    time::Duration,
+};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+/// Sets its flag when dropped.
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn join_handle_yields_output() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async { 6 * 7 });
+    assert_eq!(executor.block_on(handle), Ok(42));
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn join_waits_for_sleeping_task() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async {
+        timer::sleep(Duration::from_millis(5)).await;
+        "done"
+    });
+    assert_eq!(executor.block_on(async { handle.await }), Ok("done"));
+}
+
+#[test_case]
+fn abort_drops_future() {
+    let mut executor = Executor::new();
+    let dropped = Arc::new(AtomicBool::new(false));
+    let guard = DropFlag(dropped.clone());
+    let handle = executor.spawn(async move {
+        let _guard = guard;
+        core::future::pending::<()>().await;
+    });
+
+    let result = executor.block_on(async move {
+        // let the task start before aborting it
+        timer::sleep(Duration::from_millis(2)).await;
+        handle.abort();
+        handle.await
+    });
+    assert_eq!(result, Err(JoinError::Aborted));
+    assert!(dropped.load(Ordering::SeqCst));
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn abort_after_completion_keeps_output() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(async { 1 });
+    let result = executor.block_on(async move {
+        timer::sleep(Duration::from_millis(2)).await;
+        handle.abort();
+        handle.await
+    });
+    assert_eq!(result, Ok(1));
+}
+
+#[test_case]
+fn spawner_spawns_from_tasks() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let result = executor.block_on(async move {
+        let inner_spawner = spawner.clone();
+        let outer = spawner.spawn(async move {
+            let inner = inner_spawner.spawn(async { 2 });
+            inner.await.unwrap() + 1
+        });
+        outer.await
+    });
+    assert_eq!(result, Ok(3));
+    assert_eq!(executor.task_count(), 0);
+}
+
+/// Wakes itself several times before returning pending once, counting its
+/// polls.
+struct WakeBurst {
+    polls: Arc<AtomicUsize>,
+}
+
+impl Future for WakeBurst {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.polls.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Poll::Ready(());
+        }
+        for _ in 0..10 {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn many_tasks_and_wakeups() {
+    let mut executor = Executor::new();
+    let polls = Arc::new(AtomicUsize::new(0));
+    for _ in 0..1000 {
+        executor.spawn(WakeBurst {
+            polls: polls.clone(),
+        });
+    }
+    executor.block_on(async {});
+    // duplicate wakeups are merged, so every task is polled exactly twice
+    assert_eq!(polls.load(Ordering::SeqCst), 2000);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn higher_priority_runs_first() {
+    let mut executor = Executor::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for priority in [Priority::Background, Priority::Interactive, Priority::BottomHalf] {
+        let order = order.clone();
+        executor.spawn_with_priority(async move { order.lock().push(priority) }, priority);
+    }
+    executor.block_on(async {});
+    assert_eq!(
+        *order.lock(),
+        [Priority::BottomHalf, Priority::Interactive, Priority::Background]
+    );
+}
+
+#[test_case]
+fn yield_now_lets_others_run() {
+    let mut executor = Executor::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let handles = ['a', 'b'].map(|name| {
+        let order = order.clone();
+        executor.spawn(async move {
+            for _ in 0..3 {
+                order.lock().push(name);
+                yield_now().await;
+            }
+        })
+    });
+    executor.block_on(async move {
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    assert_eq!(*order.lock(), ['a', 'b', 'a', 'b', 'a', 'b']);
+}
+
+#[test_case]
+fn busy_task_does_not_starve_lower_priorities() {
+    let mut executor = Executor::new();
+    let busy = executor.spawn_with_priority(
+        async {
+            loop {
+                yield_now().await;
+            }
+        },
+        Priority::BottomHalf,
+    );
+    let background = executor.spawn_with_priority(async { 1 }, Priority::Background);
+    // `block_on` only returns once the background task got to run
+    assert_eq!(executor.block_on(background), Ok(1));
+    busy.abort();
+    executor.block_on(async {});
+    assert_eq!(executor.task_count(), 0);
+}
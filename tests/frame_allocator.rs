@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::memory::{self, KERNEL_MEMORY};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::memory::BitmapFrameAllocator;
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn frame_counts() {
+    let mut kernel_memory = KERNEL_MEMORY.lock();
+    let frame_allocator = &mut kernel_memory.as_mut().unwrap().frame_allocator;
+    assert!(frame_allocator.total_frames() > 0);
+    assert_eq!(
+        frame_allocator.used_frames() + frame_allocator.free_frames(),
+        frame_allocator.total_frames()
+    );
+}
+
+#[test_case]
+fn free_and_reuse_frame() {
+    let mut kernel_memory = KERNEL_MEMORY.lock();
+    let frame_allocator = &mut kernel_memory.as_mut().unwrap().frame_allocator;
+    let free_frames = frame_allocator.free_frames();
+
+    let frame: PhysFrame<Size4KiB> = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(frame_allocator.free_frames(), free_frames - 1);
+
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+
+    // the last freed frame is handed out first
+    let again: PhysFrame<Size4KiB> = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(again, frame);
+    unsafe { frame_allocator.deallocate_frame(again) };
+}
+
+#[test_case]
+fn huge_frame() {
+    let mut kernel_memory = KERNEL_MEMORY.lock();
+    let frame_allocator = &mut kernel_memory.as_mut().unwrap().frame_allocator;
+    let used_frames = frame_allocator.used_frames();
+
+    let frame: PhysFrame<Size2MiB> = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(frame.start_address().as_u64() % Size2MiB::SIZE, 0);
+    assert_eq!(frame_allocator.used_frames(), used_frames + 512);
+
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(frame_allocator.used_frames(), used_frames);
+}
@@ -13,17 +13,18 @@ entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use morb_os::allocator;
-    use morb_os::memory::{self, BootInfoFrameAllocator};
+    use morb_os::memory::{self, BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     morb_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -56,7 +57,7 @@ fn large_vec() {
     assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
 }
 
-use morb_os::allocator::HEAP_SIZE;
+use morb_os::allocator::{self, HEAP_SIZE};
 
 #[test_case]
 fn many_boxes() {
@@ -67,6 +68,7 @@ fn many_boxes() {
 }
 
 // where the bump allocator fails
+#[cfg(not(feature = "alloc-bump"))]
 #[test_case]
 fn many_boxes_long_lived() {
     let long_lived = Box::new(1);
@@ -75,4 +77,13 @@ fn many_boxes_long_lived() {
         assert_eq!(*x, i);
     }
     assert_eq!(*long_lived, 1);
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn larger_than_initial_heap() {
+    // a single allocation of 8 times the initial heap size
+    let n = HEAP_SIZE;
+    let vec = alloc::vec![1u64; n];
+    assert_eq!(vec.iter().sum::<u64>(), n as u64);
+    assert!(allocator::heap_size() > HEAP_SIZE);
+}
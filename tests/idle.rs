@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    future::Future,
+    panic::PanicInfo,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use morb_os::{
+    task::{executor::Executor, simple_executor::SimpleExecutor, timer, Task},
+    time::Duration,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+/// Counts how often the wrapped future is polled.
+struct CountPolls<F> {
+    future: Pin<Box<F>>,
+    polls: Arc<AtomicUsize>,
+}
+
+impl<F: Future> Future for CountPolls<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        self.polls.fetch_add(1, Ordering::SeqCst);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+fn sleep_counting_polls(polls: &Arc<AtomicUsize>) -> CountPolls<timer::Sleep> {
+    CountPolls {
+        future: Box::pin(timer::sleep(Duration::from_millis(20))),
+        polls: polls.clone(),
+    }
+}
+
+#[test_case]
+fn simple_executor_halts_while_waiting() {
+    let polls = Arc::new(AtomicUsize::new(0));
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(sleep_counting_polls(&polls)));
+    executor.run();
+    // polled once to start sleeping and once when woken by the timer (or
+    // twice, if the tick came just before the deadline), and halted in
+    // between instead of polling again
+    assert!((2..=3).contains(&polls.load(Ordering::SeqCst)));
+    assert!(executor.idle_halts() >= 1);
+}
+
+#[test_case]
+fn executor_halts_while_waiting() {
+    let polls = Arc::new(AtomicUsize::new(0));
+    let mut executor = Executor::new();
+    executor.block_on(sleep_counting_polls(&polls));
+    assert!((2..=3).contains(&polls.load(Ordering::SeqCst)));
+    assert!(executor.idle_halts() >= 1);
+}
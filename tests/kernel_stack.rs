@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::memory::{self, stack, vma::{self, VmAreaKind}};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::BitmapFrameAllocator;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    morb_os::gdt::init_ist_stacks().expect("IST stack allocation failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn stack_is_mapped_with_guard_page() {
+    let stack = stack::alloc_stack(4).unwrap();
+    assert_eq!(stack.size(), 4 * 4096);
+    assert!(memory::translate(stack.bottom()).is_some());
+    assert!(memory::translate(stack.top() - 8u64).is_some());
+
+    let guard = stack.guard_page().start_address();
+    assert_eq!(memory::translate(guard), None);
+    assert_eq!(vma::find(guard).map(|area| area.kind), Some(VmAreaKind::Guard));
+
+    let ptr: *mut u64 = (stack.top() - 8u64).as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+
+    let bottom = stack.bottom();
+    unsafe { stack::free_stack(stack) };
+    assert_eq!(memory::translate(bottom), None);
+    assert_eq!(vma::find(guard), None);
+}
+
+#[test_case]
+fn stacks_do_not_overlap() {
+    let first = stack::alloc_stack(stack::DEFAULT_STACK_PAGES).unwrap();
+    let second = stack::alloc_stack(stack::DEFAULT_STACK_PAGES).unwrap();
+    // the guard page of the second stack separates it from the first
+    assert!(second.guard_page().start_address() >= first.top());
+    unsafe {
+        stack::free_stack(first);
+        stack::free_stack(second);
+    }
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::lock::{IrqSafeMutex, TicketMutex};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    morb_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn lock_disables_interrupts_until_unlocked() {
+    let mutex: IrqSafeMutex<u32> = IrqSafeMutex::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = mutex.lock();
+        *value += 1;
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*mutex.lock(), 1);
+}
+
+#[test_case]
+fn interrupts_stay_disabled_if_they_were() {
+    let mutex: IrqSafeMutex<()> = IrqSafeMutex::new(());
+    interrupts::without_interrupts(|| {
+        drop(mutex.lock());
+        assert!(!interrupts::are_enabled());
+    });
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn guards_can_be_dropped_in_any_order() {
+    let first: IrqSafeMutex<()> = IrqSafeMutex::new(());
+    let second: TicketMutex<()> = TicketMutex::new(());
+    let first_guard = first.lock();
+    let second_guard = second.lock();
+    drop(first_guard);
+    // the second lock is still held
+    assert!(!interrupts::are_enabled());
+    drop(second_guard);
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn try_lock_fails_while_locked() {
+    let spin: IrqSafeMutex<()> = IrqSafeMutex::new(());
+    let guard = spin.lock();
+    assert!(spin.try_lock().is_none());
+    drop(guard);
+    assert!(spin.try_lock().is_some());
+
+    let ticket: TicketMutex<()> = TicketMutex::new(());
+    let guard = ticket.lock();
+    assert!(ticket.is_locked());
+    assert!(ticket.try_lock().is_none());
+    drop(guard);
+    assert!(!ticket.is_locked());
+    assert!(ticket.try_lock().is_some());
+    assert!(interrupts::are_enabled());
+}
+
+#[test_case]
+fn locks_taken_in_the_same_order_are_fine() {
+    let outer: IrqSafeMutex<()> = IrqSafeMutex::new(());
+    let inner: TicketMutex<()> = TicketMutex::new(());
+    for _ in 0..3 {
+        let _outer = outer.lock();
+        let _inner = inner.lock();
+    }
+    // each on its own in any order too
+    drop(inner.lock());
+    drop(outer.lock());
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use morb_os::lock::IrqSafeMutex;
+use morb_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_main();
+
+    morb_os::hlt_loop();
+}
+
+pub fn test_runner(tests: &[&dyn Fn()]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test();
+        serial_println!("[test did not panic]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    morb_os::hlt_loop();
+}
+
+static FIRST: IrqSafeMutex<()> = IrqSafeMutex::new(());
+static SECOND: IrqSafeMutex<()> = IrqSafeMutex::new(());
+
+// only debug builds check the lock order
+#[test_case]
+fn inverted_lock_order_panics() {
+    serial_print!("lock_order::inverted_lock_order_panics...\t");
+    {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    let _second = SECOND.lock();
+    let _first = FIRST.lock();
+}
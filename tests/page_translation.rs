@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use morb_os::memory::{self, MappedPageSize};
+use x86_64::{structures::paging::PageTableFlags, PhysAddr, VirtAddr};
+
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    PHYSICAL_MEMORY_OFFSET.init_once(|| phys_mem_offset);
+    unsafe { memory::init(phys_mem_offset) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn identity_mapped_vga_buffer() {
+    let translation = memory::translate(VirtAddr::new(0xb8000)).unwrap();
+    assert_eq!(translation.phys_addr, PhysAddr::new(0xb8000));
+    assert!(translation.flags.contains(PageTableFlags::PRESENT));
+}
+
+#[test_case]
+fn physical_memory_mapping() {
+    // the bootloader may map physical memory through huge pages, the offset
+    // within them has to be kept either way
+    let offset = *PHYSICAL_MEMORY_OFFSET.try_get().unwrap();
+    for &phys in &[0x1000u64, 0x20_1234, 0x40_0000 - 8] {
+        let translation = memory::translate(offset + phys).unwrap();
+        assert_eq!(translation.phys_addr, PhysAddr::new(phys));
+    }
+}
+
+#[test_case]
+fn flags_of_the_stack() {
+    let value = 0u64;
+    let translation = memory::translate(VirtAddr::from_ptr(&value)).unwrap();
+    // writable at every level, since writing it doesn't fault
+    assert!(translation.flags.contains(PageTableFlags::WRITABLE));
+}
+
+#[test_case]
+fn unmapped_address() {
+    assert_eq!(memory::translate(VirtAddr::new(0xdead_beef_0000)), None);
+}
+
+#[test_case]
+fn page_size_in_bytes() {
+    assert_eq!(MappedPageSize::Size4KiB.size(), 4096);
+    assert_eq!(MappedPageSize::Size2MiB.size(), 512 * 4096);
+    assert_eq!(MappedPageSize::Size1GiB.size(), 512 * 512 * 4096);
+}
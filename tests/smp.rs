@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::{acpi, smp, time};
+
+entry_point!(main);
+
+/// What `smp::init` returned.
+static mut ONLINE: usize = 0;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    morb_os::gdt::init_ist_stacks().expect("IST stack allocation failed");
+    acpi::init().expect("ACPI initialization failed");
+    morb_os::interrupts::apic::init().expect("APIC initialization failed");
+    unsafe { ONLINE = smp::init().expect("SMP initialization failed") };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn all_enabled_cpus_come_online() {
+    let madt = acpi::tables().unwrap().madt.as_ref().unwrap();
+    let enabled = madt.processors.iter().filter(|cpu| cpu.enabled).count();
+    let online = unsafe { ONLINE };
+    assert_eq!(online, enabled.min(smp::MAX_CPUS));
+    assert_eq!(smp::online_cpus(), online);
+}
+
+#[test_case]
+fn bsp_is_cpu_zero() {
+    assert!(smp::is_bsp());
+    assert_eq!(smp::cpu_index(), 0);
+    let local_apic = morb_os::interrupts::apic::local_apic().unwrap();
+    assert_eq!(smp::apic_id(0), Some(local_apic.id() as u32));
+}
+
+#[test_case]
+fn application_processors_get_timer_interrupts() {
+    let online = smp::online_cpus();
+    let before: [u64; smp::MAX_CPUS] = core::array::from_fn(|index| smp::ticks(index).unwrap());
+    let start = time::ticks();
+    while time::ticks() < start + 20 {
+        x86_64::instructions::hlt();
+    }
+    for index in 1..online {
+        let ticks = smp::ticks(index).unwrap();
+        assert!(
+            ticks > before[index],
+            "CPU {} got no timer interrupt",
+            index
+        );
+    }
+}
@@ -0,0 +1,232 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::{
+    task::{
+        executor::Executor,
+        sync::{Event, Mutex, Notify, RwLock, Semaphore},
+        timer, yield_now,
+    },
+    time::Duration,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn mutex_held_across_await() {
+    let mut executor = Executor::new();
+    let mutex = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let mutex = mutex.clone();
+            executor.spawn(async move {
+                let mut value = mutex.lock().await;
+                let old = *value;
+                timer::sleep(Duration::from_millis(2)).await;
+                *value = old + 1;
+            })
+        })
+        .collect();
+    executor.block_on(async move {
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    assert_eq!(*mutex.try_lock().unwrap(), 3);
+}
+
+#[test_case]
+fn mutex_waiters_are_fifo() {
+    let mut executor = Executor::new();
+    let mutex = Arc::new(Mutex::new(Vec::new()));
+    let result = executor.block_on({
+        let mutex = mutex.clone();
+        let spawner = executor.spawner();
+        async move {
+            let guard = mutex.lock().await;
+            let handles: Vec<_> = (0..3)
+                .map(|i| {
+                    let mutex = mutex.clone();
+                    spawner.spawn(async move { mutex.lock().await.push(i) })
+                })
+                .collect();
+            // let all of them queue up
+            yield_now().await;
+            yield_now().await;
+            assert!(mutex.try_lock().is_none());
+            drop(guard);
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            let order = mutex.lock().await.clone();
+            order
+        }
+    });
+    assert_eq!(result, [0, 1, 2]);
+}
+
+#[test_case]
+fn rwlock_readers_share_and_writer_waits() {
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let lock = RwLock::new(1);
+        let first = lock.read().await;
+        let second = lock.read().await;
+        assert_eq!(*first + *second, 2);
+        assert!(lock.try_write().is_none());
+        drop((first, second));
+        *lock.write().await += 1;
+        assert_eq!(*lock.read().await, 2);
+    });
+}
+
+#[test_case]
+fn rwlock_waiting_writer_blocks_new_readers() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.block_on(async move {
+        let lock = Arc::new(RwLock::new(0));
+        let reader = lock.read().await;
+        let writer = spawner.spawn({
+            let lock = lock.clone();
+            async move { *lock.write().await = 1 }
+        });
+        // once to let the executor pick up the writer, once to let it run
+        yield_now().await;
+        yield_now().await;
+        // the writer waits, so a new reader has to wait behind it
+        assert!(lock.try_read().is_none());
+        drop(reader);
+        writer.await.unwrap();
+        assert_eq!(*lock.read().await, 1);
+    });
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    let mut executor = Executor::new();
+    let semaphore = Arc::new(Semaphore::new(2));
+    let active = Arc::new(spin::Mutex::new((0, 0)));
+    let handles: Vec<_> = (0..6)
+        .map(|_| {
+            let semaphore = semaphore.clone();
+            let active = active.clone();
+            executor.spawn(async move {
+                let _permit = semaphore.acquire().await;
+                {
+                    let mut active = active.lock();
+                    active.0 += 1;
+                    active.1 = active.1.max(active.0);
+                }
+                timer::sleep(Duration::from_millis(1)).await;
+                active.lock().0 -= 1;
+            })
+        })
+        .collect();
+    executor.block_on(async move {
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    });
+    assert_eq!(active.lock().1, 2);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test_case]
+fn cancelled_acquire_returns_permits() {
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire().await;
+        let result = timer::timeout(Duration::from_millis(2), semaphore.acquire()).await;
+        assert!(result.is_err());
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 1);
+        let _permits = semaphore.acquire_many(1).await;
+        assert!(semaphore.try_acquire().is_none());
+    });
+}
+
+#[test_case]
+fn notify_one_stores_a_permit() {
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let notify = Notify::new();
+        notify.notify_one();
+        // completes right away
+        notify.notified().await;
+    });
+}
+
+#[test_case]
+fn notify_waiters_wakes_all() {
+    static NOTIFY: Notify = Notify::new();
+
+    let mut executor = Executor::new();
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            executor.spawn(async move {
+                NOTIFY.notified().await;
+                i
+            })
+        })
+        .collect();
+    let sum = executor.block_on(async move {
+        yield_now().await;
+        NOTIFY.notify_waiters();
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.unwrap();
+        }
+        sum
+    });
+    assert_eq!(sum, 3);
+}
+
+#[test_case]
+fn event_wakes_waiters_until_reset() {
+    static EVENT: Event = Event::new();
+
+    let mut executor = Executor::new();
+    let waiter = executor.spawn(async { EVENT.wait().await });
+    executor.block_on(async move {
+        yield_now().await;
+        assert!(!waiter.is_finished());
+        EVENT.set();
+        waiter.await.unwrap();
+        // stays set
+        EVENT.wait().await;
+        EVENT.reset();
+        assert!(!EVENT.is_set());
+    });
+}
@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use morb_os::{
+    task::{executor::Executor, timer},
+    thread,
+    time::{Duration, Instant},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    thread::init().expect("thread initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn join_returns_output() {
+    let handle = thread::spawn(|| 6 * 7).unwrap();
+    assert_ne!(handle.id(), thread::current());
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn busy_threads_are_preempted() {
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    let handle = thread::spawn(|| {
+        STARTED.store(true, Ordering::SeqCst);
+        let mut spins = 0u64;
+        while !STOP.load(Ordering::SeqCst) {
+            spins += 1;
+        }
+        spins
+    })
+    .unwrap();
+    // neither thread yields, so this only ends if the timer switches threads
+    while !STARTED.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::SeqCst);
+    handle.join();
+}
+
+#[test_case]
+fn sleep_waits() {
+    let start = Instant::now();
+    thread::sleep(Duration::from_millis(10));
+    assert!(start.elapsed() >= Duration::from_millis(10));
+}
+
+#[test_case]
+fn many_threads() {
+    static SUM: AtomicU64 = AtomicU64::new(0);
+
+    let handles: Vec<_> = (1..=20)
+        .map(|i| {
+            thread::spawn(move || {
+                thread::yield_now();
+                SUM.fetch_add(i, Ordering::SeqCst);
+                i * 2
+            })
+            .unwrap()
+        })
+        .collect();
+    let doubled: u64 = handles.into_iter().map(|handle| handle.join()).sum();
+    assert_eq!(SUM.load(Ordering::SeqCst), 210);
+    assert_eq!(doubled, 420);
+}
+
+#[test_case]
+fn detached_threads_finish() {
+    static DONE: AtomicU64 = AtomicU64::new(0);
+
+    for _ in 0..10 {
+        drop(thread::spawn(|| DONE.fetch_add(1, Ordering::SeqCst)).unwrap());
+    }
+    while DONE.load(Ordering::SeqCst) < 10 {
+        thread::yield_now();
+    }
+    // spawning frees the stacks of the finished detached threads
+    thread::spawn(|| ()).unwrap().join();
+}
+
+#[test_case]
+fn executor_runs_in_a_thread() {
+    let handle = thread::spawn(|| {
+        Executor::new().block_on(async {
+            timer::sleep(Duration::from_millis(5)).await;
+            7
+        })
+    })
+    .unwrap();
+    // keeps running while the executor waits for the timer
+    thread::sleep(Duration::from_millis(1));
+    assert_eq!(handle.join(), 7);
+}
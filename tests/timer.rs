@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, task::Wake};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    future::Future,
+    panic::PanicInfo,
+    pin::pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use morb_os::{
+    task::timer::{self, Elapsed},
+    time::{Duration, Instant},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+/// Remembers whether it was woken.
+struct FlagWaker(AtomicBool);
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Polls `future` whenever its waker was woken, halting in between.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let flag = Arc::new(FlagWaker(AtomicBool::new(true)));
+    let waker = Waker::from(flag.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if flag.0.swap(false, Ordering::SeqCst) {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn sleep_waits_for_duration() {
+    let start = Instant::now();
+    block_on(timer::sleep(Duration::from_millis(30)));
+    assert!(start.elapsed() >= Duration::from_millis(30));
+}
+
+#[test_case]
+fn sleep_until_past_deadline_is_ready() {
+    block_on(timer::sleep_until(Instant::BOOT));
+}
+
+#[test_case]
+fn timeout_elapses() {
+    let result = block_on(timer::timeout(
+        Duration::from_millis(10),
+        core::future::pending::<()>(),
+    ));
+    assert_eq!(result, Err(Elapsed));
+}
+
+#[test_case]
+fn timeout_completes() {
+    let result = block_on(timer::timeout(Duration::from_secs(1), async {
+        timer::sleep(Duration::from_millis(5)).await;
+        42
+    }));
+    assert_eq!(result, Ok(42));
+}
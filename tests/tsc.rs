@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(morb_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use morb_os::time::{self, tsc, Duration, Instant};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use morb_os::allocator;
+    use morb_os::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    morb_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    // QEMU only reports an invariant TSC with `+invtsc`, see the test args
+    morb_os::acpi::init().expect("ACPI initialization failed");
+    tsc::init().expect("TSC calibration failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    morb_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn now_is_monotonic() {
+    let mut last = time::now();
+    for _ in 0..10_000 {
+        let now = time::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn now_agrees_with_timer_ticks() {
+    let start_tick = time::ticks();
+    let start = time::now();
+    while time::ticks() < start_tick + 50 {
+        x86_64::instructions::hlt();
+    }
+    let elapsed = start.elapsed();
+    // 49 to 51 ticks of 1 ms have passed, allow for calibration error
+    assert!(elapsed >= Duration::from_millis(45), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(56), "{:?}", elapsed);
+}
+
+#[test_case]
+fn tsc_has_sub_tick_resolution() {
+    assert!(tsc::frequency().is_some());
+    let start = Instant::now();
+    let mut end = Instant::now();
+    while end == start {
+        end = Instant::now();
+    }
+    assert!(end - start < Duration::from_micros(100));
+}